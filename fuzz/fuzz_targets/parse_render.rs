@@ -0,0 +1,77 @@
+// OpenTimestamps Viewer
+// Written in 2017 by
+//   Andrew Poelstra <rust-ots@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Parse and Render Fuzz Target
+//!
+//! Feeds arbitrary bytes through the timestamp parser and everything which
+//! handles the parsed tree: the renderers, merging, comparison, pruning
+//! and document IDs. None of these may panic or overflow the stack.
+//!
+
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate opentimestamps as ots;
+extern crate ots_viewer;
+
+use ots::DetachedTimestampFile;
+use ots_viewer::{canonical, graph, merge, prune, render, tree};
+use ots_viewer::chain::{self, Header, HeaderSource, HEADER_LEN};
+
+/// A header source in which every block has the same Merkle root, so that
+/// attestations to it verify
+struct FuzzChain(Vec<u8>);
+
+impl HeaderSource for FuzzChain {
+    fn header_at(&self, height: usize) -> Result<Option<Header>, chain::Error> {
+        if self.0.len() != 32 {
+            return Ok(None);
+        }
+        let mut data = vec![0; HEADER_LEN];
+        data[36..68].copy_from_slice(&self.0);
+        data[68] = height as u8;
+        Ok(Header::from_bytes(&data))
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    let dtf = match DetachedTimestampFile::from_reader(data) {
+        Ok(dtf) => dtf,
+        Err(_) => return
+    };
+    let limits = tree::Limits::default();
+    let first_step = &dtf.timestamp.first_step;
+    let start_digest = &dtf.timestamp.start_digest;
+    // Attestations directly to the document digest verify
+    let chain = FuzzChain(start_digest.clone());
+
+    if let Ok(tree) = render::render_tree(first_step, start_digest, Some(&chain), &limits) {
+        render::render_verdict(&tree);
+    }
+    let _ = render::render_paths(first_step, start_digest, Some(&chain), &limits, None);
+    let _ = render::render_paths(first_step, start_digest, Some(&chain), &limits, Some(1));
+    let _ = graph::render_graph(first_step, start_digest, Some(&chain), &limits);
+    let _ = prune::prune(&dtf, Some(&chain), &limits, prune::Preference::Shortest);
+    let _ = prune::prune(&dtf, Some(&chain), &limits, prune::Preference::Earliest);
+    canonical::hash(&dtf);
+
+    // Merging and comparison consume their inputs, so parse afresh
+    if let (Ok(first), Ok(second)) = (DetachedTimestampFile::from_reader(data), DetachedTimestampFile::from_reader(data)) {
+        if let Ok(merged) = merge::merge(vec![first, second]) {
+            canonical::hash(&merged);
+        }
+    }
+    if let (Ok(first), Ok(second)) = (DetachedTimestampFile::from_reader(data), DetachedTimestampFile::from_reader(data)) {
+        let _ = merge::compare(first, second);
+    }
+});
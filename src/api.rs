@@ -0,0 +1,560 @@
+// OpenTimestamps Viewer
+// Written in 2017 by
+//   Andrew Poelstra <rust-ots@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # API
+//!
+//! JSON interface for inspecting timestamps, for use by scripts
+//!
+
+use std::io::{self, Read};
+
+use ots::DetachedTimestampFile;
+use ots::attestation::Attestation;
+use ots::hex::Hexed;
+use ots::op::Op;
+use ots::timestamp::{Step, StepData};
+use rocket::{Data, State};
+use rocket::http::{ContentType, Status};
+use rocket::response::status;
+use rocket_contrib::json::Json;
+
+use cache::{self, Cache, DocId};
+use calendar::{Calendars, UpgradeReport};
+use chain::{self, reversed, Chain, HeaderSource, Verification};
+use tree::{self, Limits, Visit};
+use upload::{self, SIZE_LIMIT};
+
+/// Deepest tree returned as JSON, whatever the configured tree limits
+const MAX_JSON_DEPTH: usize = 1024;
+
+/// An error returned by the API
+#[derive(Serialize)]
+pub struct ApiError {
+    error: String
+}
+
+/// A status code with a JSON error
+pub type ApiFailure = status::Custom<Json<ApiError>>;
+
+/// Result of an API call: either JSON or a status code with a JSON error
+pub type ApiResult<T> = Result<Json<T>, ApiFailure>;
+
+/// Construct an API error
+pub fn failure(status: Status, message: String) -> ApiFailure {
+    status::Custom(status, Json(ApiError { error: message }))
+}
+
+/// Construct an API error response
+pub fn error<T>(status: Status, message: String) -> ApiResult<T> {
+    Err(failure(status, message))
+}
+
+/// A newly stored timestamp
+#[derive(Serialize)]
+pub struct StoredTimestamp {
+    id: DocId,
+    view_url: String,
+    download_url: String
+}
+
+/// A parsed timestamp
+#[derive(Serialize)]
+pub struct JsonTimestamp {
+    id: DocId,
+    digest_type: String,
+    start_digest: String,
+    tree: JsonStep
+}
+
+/// A single step of a timestamp, with everything that follows it
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum JsonStep {
+    /// The timestamp branches into several independent paths
+    Fork {
+        branches: Vec<JsonStep>
+    },
+    /// An operation on the output of the previous step
+    Op {
+        op: &'static str,
+        argument: Option<String>,
+        input: String,
+        output: String,
+        next: Box<JsonStep>
+    },
+    /// An attestation to the output of the previous step
+    Attestation {
+        input: String,
+        attestation: JsonAttestation
+    }
+}
+
+/// An attestation
+#[derive(Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum JsonAttestation {
+    /// Attestation that the input is the Merkle root of a Bitcoin block
+    Bitcoin {
+        height: usize,
+        merkle_root: String,
+        verification: JsonVerification
+    },
+    /// Promise by a calendar server to produce a complete attestation later
+    Pending {
+        uri: String
+    },
+    /// Attestation of a type we do not understand
+    Unknown {
+        tag: String,
+        data: String
+    }
+}
+
+/// Result of checking a Bitcoin attestation against the chain
+#[derive(Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum JsonVerification {
+    /// No header source is configured
+    Unchecked,
+    /// The block has the attested Merkle root
+    Verified {
+        block_hash: String,
+        time: u32,
+        median_time_past: u32
+    },
+    /// The block has some other Merkle root
+    Mismatch {
+        block_merkle_root: String
+    },
+    /// The header source does not know the block
+    UnknownHeight,
+    /// The header source could not be queried
+    Error {
+        message: String
+    }
+}
+
+fn hex(data: &[u8]) -> String {
+    format!("{}", Hexed(data))
+}
+
+fn json_verification(chain: Option<&dyn HeaderSource>, height: usize, merkle_root: &[u8]) -> JsonVerification {
+    let source = match chain {
+        Some(source) => source,
+        None => return JsonVerification::Unchecked
+    };
+    match chain::verify(source, height, merkle_root) {
+        Ok(Verification::Verified { header, median_time_past }) => JsonVerification::Verified {
+            block_hash: hex(&reversed(&header.block_hash())),
+            time: header.time(),
+            median_time_past: median_time_past
+        },
+        Ok(Verification::Mismatch(header)) => JsonVerification::Mismatch {
+            block_merkle_root: hex(&reversed(header.merkle_root()))
+        },
+        Ok(Verification::UnknownHeight) => JsonVerification::UnknownHeight,
+        Err(e) => JsonVerification::Error { message: e.to_string() }
+    }
+}
+
+/// Convert a single step to JSON, given the JSON of the steps following it
+fn json_step(visit: Visit, next: Vec<JsonStep>, chain: Option<&dyn HeaderSource>) -> JsonStep {
+    let prev_data = visit.input;
+    let step = visit.step;
+    match step.data {
+        StepData::Fork => JsonStep::Fork {
+            branches: next
+        },
+        StepData::Op(ref op) => {
+            let (name, argument) = match *op {
+                Op::Sha1 => ("sha1", None),
+                Op::Sha256 => ("sha256", None),
+                Op::Ripemd160 => ("ripemd160", None),
+                Op::Hexlify => ("hexlify", None),
+                Op::Reverse => ("reverse", None),
+                Op::Append(ref data) => ("append", Some(hex(data))),
+                Op::Prepend(ref data) => ("prepend", Some(hex(data)))
+            };
+            // The walk fails on operations without a next step, so this
+            // is never empty
+            let next = match next.into_iter().next() {
+                Some(next) => next,
+                None => JsonStep::Fork { branches: vec![] }
+            };
+            JsonStep::Op {
+                op: name,
+                argument: argument,
+                input: hex(prev_data),
+                output: hex(&step.output),
+                next: Box::new(next)
+            }
+        }
+        StepData::Attestation(ref attest) => {
+            let attestation = match *attest {
+                Attestation::Bitcoin { height } => JsonAttestation::Bitcoin {
+                    height: height,
+                    merkle_root: hex(&reversed(prev_data)),
+                    verification: json_verification(chain, height, prev_data)
+                },
+                Attestation::Pending { ref uri } => JsonAttestation::Pending {
+                    uri: uri.clone()
+                },
+                Attestation::Unknown { ref tag, ref data } => JsonAttestation::Unknown {
+                    tag: hex(tag),
+                    data: hex(data)
+                }
+            };
+            JsonStep::Attestation {
+                input: hex(prev_data),
+                attestation: attestation
+            }
+        }
+    }
+}
+
+/// Convert a timestamp to its JSON representation
+pub fn json_timestamp(dtf: &DetachedTimestampFile, chain: Option<&dyn HeaderSource>, limits: &Limits) -> ApiResult<JsonTimestamp> {
+    // Serializing (and dropping) nested JSON is recursive, so however deep
+    // the configured limits allow trees to be, we cap the depth here
+    let limits = Limits {
+        max_depth: limits.max_depth.min(MAX_JSON_DEPTH),
+        max_steps: limits.max_steps
+    };
+    let start_digest = &dtf.timestamp.start_digest;
+    let json = tree::fold(&dtf.timestamp.first_step, start_digest, &limits, |visit, next| json_step(visit, next, chain));
+    match json {
+        Ok(json) => Ok(Json(JsonTimestamp {
+            id: cache::doc_id(dtf),
+            digest_type: format!("{}", dtf.digest_type),
+            start_digest: hex(start_digest),
+            tree: json
+        })),
+        Err(e) => error(Status::UnprocessableEntity, e.to_string())
+    }
+}
+
+// Cached timestamp viewer
+#[get("/api/v1/view/<id>")]
+pub fn view(id: DocId, cache: State<Cache>, chain: State<Chain>, limits: State<Limits>) -> ApiResult<JsonTimestamp> {
+    match cache.load(&cache.resolve(&id)) {
+        Ok(dtf) => json_timestamp(&dtf, chain.source(), &limits),
+        Err(cache::Error::Io(ref e)) if e.kind() == io::ErrorKind::NotFound => {
+            error(Status::NotFound, "no such timestamp".to_owned())
+        }
+        Err(e) => error(Status::InternalServerError, e.to_string())
+    }
+}
+
+/// Read and parse a timestamp from a raw request body
+fn read_timestamp(data: Data) -> Result<DetachedTimestampFile, ApiFailure> {
+    let mut body = vec![];
+    if let Err(e) = data.open().take(SIZE_LIMIT + 1).read_to_end(&mut body) {
+        return Err(failure(Status::BadRequest, e.to_string()));
+    }
+    if body.len() as u64 > SIZE_LIMIT {
+        return Err(failure(Status::PayloadTooLarge, format!("timestamp exceeds {} bytes", SIZE_LIMIT)));
+    }
+    upload::parse(&body[..]).map_err(|e| failure(e.status(), e.to_string()))
+}
+
+// Inspect an uploaded timestamp without storing it
+#[post("/api/v1/inspect", data = "<data>")]
+pub fn inspect(data: Data, chain: State<Chain>, limits: State<Limits>) -> ApiResult<JsonTimestamp> {
+    let dtf = read_timestamp(data)?;
+    json_timestamp(&dtf, chain.source(), &limits)
+}
+
+// Store an uploaded timestamp
+#[post("/api/v1/timestamps", data = "<data>")]
+pub fn create(content_type: Option<&ContentType>, data: Data, cache: State<Cache>) -> Result<status::Created<Json<StoredTimestamp>>, ApiFailure> {
+    let acceptable = match content_type {
+        Some(ct) => ct.top() == "application" && (ct.sub() == "octet-stream" || ct.sub() == "vnd.opentimestamps"),
+        None => false
+    };
+    if !acceptable {
+        return Err(failure(Status::UnsupportedMediaType, "expected application/octet-stream or application/vnd.opentimestamps".to_owned()));
+    }
+
+    let dtf = read_timestamp(data)?;
+    match cache.store(&dtf) {
+        Ok(id) => {
+            let stored = StoredTimestamp {
+                view_url: format!("/view/{}", id),
+                download_url: format!("/download/{}", id),
+                id: id
+            };
+            Ok(status::Created(format!("/api/v1/view/{}", stored.id), Some(Json(stored))))
+        }
+        Err(e) => Err(failure(Status::InternalServerError, e.to_string()))
+    }
+}
+
+// Upgrade a cached timestamp
+#[post("/api/v1/upgrade/<id>")]
+pub fn upgrade(id: DocId, calendars: State<Calendars>, cache: State<Cache>) -> ApiResult<UpgradeReport> {
+    match calendars.upgrade_cached(&cache, &id) {
+        Ok(response) => Ok(Json(response)),
+        Err(cache::Error::Io(ref e)) if e.kind() == io::ErrorKind::NotFound => {
+            error(Status::NotFound, "no such timestamp".to_owned())
+        }
+        Err(e) => error(Status::InternalServerError, e.to_string())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+    use std::path::{Path, PathBuf};
+    use std::time::Duration;
+
+    use ots::DetachedTimestampFile;
+    use ots::hex::Hexed;
+    use ots::op::Op;
+    use ots::ser::DigestType;
+    use rocket;
+    use rocket::http::{ContentType, Status};
+    use rocket::local::Client;
+    use rocket::response::status;
+    use serde_json::{self, Value};
+
+    use cache::{self, Cache};
+    use calendar::Calendars;
+    use chain::{reversed, Chain};
+    use testutil::{bitcoin, file, fork, op, pending, serialized, DIGEST};
+    use tree::Limits;
+
+    use upload::SIZE_LIMIT;
+
+    use super::{json_timestamp, MAX_JSON_DEPTH};
+
+    /// A fresh directory for a cache, unique to the calling test
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("ots-viewer-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// A client for the API alone, over a cache in `dir`, with no header
+    /// source and no calendars
+    fn client(dir: &Path, limits: Limits) -> Client {
+        let calendars = Calendars::new(vec![], Duration::from_secs(1), limits).unwrap();
+        let rocket = rocket::ignite()
+            .manage(Cache::new(dir))
+            .manage(Chain(None))
+            .manage(limits)
+            .manage(calendars)
+            .mount("/", routes![super::view, super::inspect, super::create, super::upgrade]);
+        Client::new(rocket).unwrap()
+    }
+
+    /// A timestamp with a Bitcoin attestation after an operation, beside
+    /// a pending attestation
+    fn timestamp() -> DetachedTimestampFile {
+        file(fork(vec![
+            op(Op::Append(vec![0x01]), bitcoin(100)),
+            pending("https://a.pool.opentimestamps.org")
+        ]))
+    }
+
+    fn hex(data: &[u8]) -> String {
+        format!("{}", Hexed(data))
+    }
+
+    /// Check the JSON returned for `timestamp()`
+    fn check_json(json: &Value) {
+        let dtf = timestamp();
+        let appended = &dtf.timestamp.first_step.next[0].output;
+        assert_eq!(json["id"].as_str(), Some(&*cache::doc_id(&dtf).to_string()));
+        assert_eq!(json["digest_type"].as_str(), Some(&*format!("{}", DigestType::Sha256)));
+        assert_eq!(json["start_digest"].as_str(), Some(&*hex(&DIGEST)));
+
+        let tree = &json["tree"];
+        assert_eq!(tree["type"].as_str(), Some("fork"));
+        assert_eq!(tree["branches"].as_array().map(|b| b.len()), Some(2));
+
+        let append = &tree["branches"][0];
+        assert_eq!(append["type"].as_str(), Some("op"));
+        assert_eq!(append["op"].as_str(), Some("append"));
+        assert_eq!(append["argument"].as_str(), Some("01"));
+        assert_eq!(append["input"].as_str(), Some(&*hex(&DIGEST)));
+        assert_eq!(append["output"].as_str(), Some(&*hex(appended)));
+        let attestation = &append["next"];
+        assert_eq!(attestation["type"].as_str(), Some("attestation"));
+        assert_eq!(attestation["input"].as_str(), Some(&*hex(appended)));
+        assert_eq!(attestation["attestation"]["kind"].as_str(), Some("bitcoin"));
+        assert_eq!(attestation["attestation"]["height"].as_u64(), Some(100));
+        assert_eq!(attestation["attestation"]["merkle_root"].as_str(), Some(&*hex(&reversed(appended))));
+        assert_eq!(attestation["attestation"]["verification"]["status"].as_str(), Some("unchecked"));
+
+        let pending = &tree["branches"][1];
+        assert_eq!(pending["type"].as_str(), Some("attestation"));
+        assert_eq!(pending["input"].as_str(), Some(&*hex(&DIGEST)));
+        assert_eq!(pending["attestation"]["kind"].as_str(), Some("pending"));
+        assert_eq!(pending["attestation"]["uri"].as_str(), Some("https://a.pool.opentimestamps.org"));
+    }
+
+    #[test]
+    fn view() {
+        let dir = temp_dir("api-view");
+        let id = Cache::new(&dir).store(&timestamp()).unwrap();
+        let client = client(&dir, Limits::default());
+
+        let mut response = client.get(format!("/api/v1/view/{}", id)).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.content_type(), Some(ContentType::JSON));
+        let json: Value = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        check_json(&json);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn inspect() {
+        let dir = temp_dir("api-inspect");
+        let client = client(&dir, Limits::default());
+
+        let mut response = client.post("/api/v1/inspect").body(serialized(&timestamp())).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let json: Value = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        check_json(&json);
+        // Nothing was stored
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// A timestamp whose attestation follows `ops` operations
+    fn deep_timestamp(ops: usize) -> DetachedTimestampFile {
+        let mut step = bitcoin(1);
+        for _ in 0..ops {
+            step = op(Op::Reverse, step);
+        }
+        file(step)
+    }
+
+    #[test]
+    fn json_depth() {
+        // The configured limits allow deeper trees than JSON does
+        let limits = Limits { max_depth: 4 * MAX_JSON_DEPTH, ..Limits::default() };
+        assert!(json_timestamp(&deep_timestamp(MAX_JSON_DEPTH - 1), None, &limits).is_ok());
+        match json_timestamp(&deep_timestamp(MAX_JSON_DEPTH), None, &limits) {
+            Err(status::Custom(status, _)) => assert_eq!(status, Status::UnprocessableEntity),
+            Ok(_) => panic!("expected the tree to be too deep")
+        }
+    }
+
+    /// The error message of a failed request
+    fn error_message(body: Option<String>) -> String {
+        let json: Value = serde_json::from_str(&body.unwrap()).unwrap();
+        json["error"].as_str().unwrap().to_owned()
+    }
+
+    #[test]
+    fn create() {
+        let dir = temp_dir("api-create");
+        let client = client(&dir, Limits::default());
+        let id = cache::doc_id(&timestamp());
+
+        let mut response = client.post("/api/v1/timestamps")
+            .header(ContentType::new("application", "vnd.opentimestamps"))
+            .body(serialized(&timestamp()))
+            .dispatch();
+        assert_eq!(response.status(), Status::Created);
+        assert_eq!(response.headers().get_one("Location"), Some(&*format!("/api/v1/view/{}", id)));
+        let json: Value = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        assert_eq!(json["id"].as_str(), Some(&*id.to_string()));
+        assert_eq!(json["view_url"].as_str(), Some(&*format!("/view/{}", id)));
+        assert_eq!(json["download_url"].as_str(), Some(&*format!("/download/{}", id)));
+        assert!(Cache::new(&dir).load(&id).is_ok());
+
+        let response = client.post("/api/v1/timestamps")
+            .header(ContentType::Binary)
+            .body(serialized(&timestamp()))
+            .dispatch();
+        assert_eq!(response.status(), Status::Created);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn create_failures() {
+        let dir = temp_dir("api-create-failures");
+        let client = client(&dir, Limits::default());
+        let data = serialized(&timestamp());
+
+        // Anything but a timestamp file is refused before reading it
+        let response = client.post("/api/v1/timestamps").body(&data).dispatch();
+        assert_eq!(response.status(), Status::UnsupportedMediaType);
+        let mut response = client.post("/api/v1/timestamps").header(ContentType::JSON).body(&data).dispatch();
+        assert_eq!(response.status(), Status::UnsupportedMediaType);
+        assert!(error_message(response.body_string()).contains("application/octet-stream"));
+
+        // Files which do not parse, whether they are timestamps or not
+        let mut response = client.post("/api/v1/timestamps").header(ContentType::Binary).body("not a timestamp").dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+        assert!(!error_message(response.body_string()).is_empty());
+        let response = client.post("/api/v1/timestamps").header(ContentType::Binary).body(&data[..data.len() - 1]).dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+
+        // Anything over the size limit, however it would have parsed
+        let mut large = data.clone();
+        large.resize(SIZE_LIMIT as usize + 1, 0);
+        let mut response = client.post("/api/v1/timestamps").header(ContentType::Binary).body(large).dispatch();
+        assert_eq!(response.status(), Status::PayloadTooLarge);
+        assert_eq!(error_message(response.body_string()), format!("timestamp exceeds {} bytes", SIZE_LIMIT));
+        let response = client.post("/api/v1/inspect").body(vec![0; SIZE_LIMIT as usize + 1]).dispatch();
+        assert_eq!(response.status(), Status::PayloadTooLarge);
+
+        // Nothing was stored
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn not_found() {
+        let dir = temp_dir("api-not-found");
+        let client = client(&dir, Limits::default());
+        let id = cache::doc_id(&timestamp());
+
+        let mut response = client.get(format!("/api/v1/view/{}", id)).dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+        assert_eq!(error_message(response.body_string()), "no such timestamp");
+        let mut response = client.post(format!("/api/v1/upgrade/{}", id)).dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+        assert_eq!(error_message(response.body_string()), "no such timestamp");
+        // Malformed IDs never reach the handlers
+        let response = client.get("/api/v1/view/not-an-id").dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn over_limits() {
+        let dir = temp_dir("api-over-limits");
+        let id = Cache::new(&dir).store(&timestamp()).unwrap();
+        // The attestation after the operation is two steps below the root
+        let client = client(&dir, Limits { max_depth: 2, ..Limits::default() });
+
+        let response = client.get(format!("/api/v1/view/{}", id)).dispatch();
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let mut response = client.post("/api/v1/inspect").body(serialized(&timestamp())).dispatch();
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        assert!(!error_message(response.body_string()).is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,445 @@
+// OpenTimestamps Viewer
+// Written in 2017 by
+//   Andrew Poelstra <rust-ots@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Bitcoind
+//!
+//! Header source backed by the JSON-RPC interface of a Bitcoin Core node
+//!
+//! Everything we need to know about a block comes from one verbose
+//! `getblockheader` call, whose result is cached once the block is buried
+//! deeply enough, since every page view checks the same attestations.
+//!
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::Read;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use hex;
+use reqwest;
+use serde_json;
+
+use chain::{reversed, Error, Header, HeaderSource};
+
+/// RPC error code returned by `getblockhash` for heights past the tip
+const RPC_INVALID_PARAMETER: i64 = -8;
+
+/// Blocks with fewer confirmations than this are not cached, since they
+/// may yet be reorganized away
+const MIN_CACHED_CONFIRMATIONS: u64 = 6;
+
+/// Most blocks cached at once
+const MAX_CACHED_BLOCKS: usize = 4096;
+
+/// How to authenticate to bitcoind
+pub enum Auth {
+    /// No authentication
+    None,
+    /// Explicit `rpcuser`/`rpcpassword`
+    UserPass(String, String),
+    /// Path to a `.cookie` file, re-read on every request since it
+    /// changes whenever the node restarts
+    Cookie(PathBuf)
+}
+
+#[derive(Serialize)]
+struct Request<'a> {
+    jsonrpc: &'static str,
+    id: &'static str,
+    method: &'a str,
+    params: Vec<serde_json::Value>
+}
+
+#[derive(Deserialize)]
+struct Response {
+    result: Option<serde_json::Value>,
+    error: Option<RpcError>
+}
+
+#[derive(Deserialize)]
+struct RpcError {
+    code: i64,
+    message: String
+}
+
+/// What we know about a block
+#[derive(Clone)]
+struct BlockInfo {
+    header: Header,
+    median_time_past: u32,
+    /// Number of transactions, which nodes older than 0.17 do not report
+    tx_count: Option<usize>
+}
+
+/// A connection to a bitcoind JSON-RPC endpoint
+pub struct Bitcoind {
+    url: String,
+    auth: Auth,
+    client: reqwest::Client,
+    /// Blocks already looked up, by height
+    cache: Mutex<HashMap<usize, BlockInfo>>
+}
+
+impl Bitcoind {
+    /// Create a new RPC client; no connection is made until a header is requested
+    pub fn new(url: String, auth: Auth, timeout: Duration) -> Result<Bitcoind, Error> {
+        let client = reqwest::Client::builder()
+            .timeout(timeout)
+            .build()
+            .map_err(|e| Error::Rpc(format!("failed to create HTTP client: {}", e)))?;
+        Ok(Bitcoind {
+            url: url,
+            auth: auth,
+            client: client,
+            cache: Mutex::new(HashMap::new())
+        })
+    }
+
+    /// Make a single RPC call, returning its result or the error reported by bitcoind
+    fn call(&self, method: &str, params: Vec<serde_json::Value>) -> Result<Result<serde_json::Value, RpcError>, Error> {
+        let request = Request {
+            jsonrpc: "1.0",
+            id: "ots-viewer",
+            method: method,
+            params: params
+        };
+        let mut builder = self.client.post(&self.url).json(&request);
+        match self.auth {
+            Auth::None => {}
+            Auth::UserPass(ref user, ref pass) => {
+                builder = builder.basic_auth(user, Some(pass));
+            }
+            Auth::Cookie(ref path) => {
+                let mut cookie = String::new();
+                fs::File::open(path)?.read_to_string(&mut cookie)?;
+                let mut split = cookie.trim().splitn(2, ':');
+                let user = split.next().unwrap_or("").to_owned();
+                let pass = split.next().unwrap_or("").to_owned();
+                builder = builder.basic_auth(user, Some(pass));
+            }
+        }
+
+        let mut http_response = builder.send()
+            .map_err(|e| Error::Rpc(format!("{} failed: {}", method, e)))?;
+        // bitcoind reports RPC errors with a 500 status and a JSON body, so
+        // only treat the status as fatal if there is no JSON to be had
+        let status = http_response.status();
+        let response: Response = match http_response.json() {
+            Ok(response) => response,
+            Err(e) => return Err(Error::Rpc(format!("{} failed: HTTP {} ({})", method, status, e)))
+        };
+        match (response.result, response.error) {
+            (_, Some(error)) => Ok(Err(error)),
+            (Some(result), None) => Ok(Ok(result)),
+            (None, None) => Err(Error::Rpc(format!("{} returned neither result nor error", method)))
+        }
+    }
+
+    /// Look up the hash of the block at `height`, if the node has one
+    fn block_hash_at(&self, height: usize) -> Result<Option<String>, Error> {
+        match self.call("getblockhash", vec![height.into()])? {
+            Ok(serde_json::Value::String(hash)) => Ok(Some(hash)),
+            Ok(_) => Err(Error::Rpc("getblockhash did not return a string".to_owned())),
+            Err(ref e) if e.code == RPC_INVALID_PARAMETER => Ok(None),
+            Err(e) => Err(Error::Rpc(format!("getblockhash: {} (code {})", e.message, e.code)))
+        }
+    }
+
+    /// Call `getblockheader` on the given hash, asking for the JSON object
+    fn block_header(&self, hash: &str) -> Result<serde_json::Value, Error> {
+        match self.call("getblockheader", vec![hash.into(), true.into()])? {
+            Ok(result) => Ok(result),
+            Err(e) => Err(Error::Rpc(format!("getblockheader: {} (code {})", e.message, e.code)))
+        }
+    }
+
+    /// Look up the block at `height`, if the node has one
+    fn block_info(&self, height: usize) -> Result<Option<BlockInfo>, Error> {
+        if let Ok(cache) = self.cache.lock() {
+            if let Some(info) = cache.get(&height) {
+                return Ok(Some(info.clone()));
+            }
+        }
+
+        let hash = match self.block_hash_at(height)? {
+            Some(hash) => hash,
+            None => return Ok(None)
+        };
+        let result = self.block_header(&hash)?;
+        let info = match parse_block_info(&result, &hash) {
+            Some(info) => info,
+            None => return Err(Error::Rpc("getblockheader returned a malformed header".to_owned()))
+        };
+
+        let confirmations = result.get("confirmations").and_then(|c| c.as_u64()).unwrap_or(0);
+        if confirmations >= MIN_CACHED_CONFIRMATIONS {
+            if let Ok(mut cache) = self.cache.lock() {
+                if cache.len() >= MAX_CACHED_BLOCKS {
+                    cache.clear();
+                }
+                cache.insert(height, info.clone());
+            }
+        }
+        Ok(Some(info))
+    }
+}
+
+/// Append a little-endian 32-bit integer
+fn push_u32(data: &mut Vec<u8>, n: u32) {
+    for i in 0..4 {
+        data.push((n >> (8 * i)) as u8);
+    }
+}
+
+/// Decode a hash given in hex in display order, i.e. reversed
+fn parse_hash(value: &serde_json::Value) -> Option<Vec<u8>> {
+    let hash = hex::decode(value.as_str()?).ok()?;
+    if hash.len() == 32 {
+        Some(reversed(&hash))
+    } else {
+        None
+    }
+}
+
+/// Reassemble the header from the result of a verbose `getblockheader`,
+/// checking that it hashes to `hash`
+fn parse_block_info(result: &serde_json::Value, hash: &str) -> Option<BlockInfo> {
+    let mut data = Vec::with_capacity(80);
+    push_u32(&mut data, result.get("version")?.as_i64()? as u32);
+    // Only the genesis block has no predecessor
+    match result.get("previousblockhash") {
+        Some(prev) => data.extend(parse_hash(prev)?),
+        None => data.extend_from_slice(&[0; 32])
+    }
+    data.extend(parse_hash(result.get("merkleroot")?)?);
+    push_u32(&mut data, result.get("time")?.as_u64()? as u32);
+    push_u32(&mut data, u32::from_str_radix(result.get("bits")?.as_str()?, 16).ok()?);
+    push_u32(&mut data, result.get("nonce")?.as_u64()? as u32);
+
+    let header = Header::from_bytes(&data)?;
+    if hex::encode(reversed(&header.block_hash())) != hash {
+        return None;
+    }
+    Some(BlockInfo {
+        header: header,
+        median_time_past: result.get("mediantime")?.as_u64()? as u32,
+        tx_count: result.get("nTx").and_then(|n| n.as_u64()).map(|n| n as usize)
+    })
+}
+
+impl HeaderSource for Bitcoind {
+    fn header_at(&self, height: usize) -> Result<Option<Header>, Error> {
+        Ok(self.block_info(height)?.map(|info| info.header))
+    }
+
+    // bitcoind will compute this for us, saving ten round trips
+    fn median_time_past(&self, height: usize) -> Result<Option<u32>, Error> {
+        Ok(self.block_info(height)?.map(|info| info.median_time_past))
+    }
+
+    fn tx_count(&self, height: usize) -> Result<Option<usize>, Error> {
+        Ok(self.block_info(height)?.and_then(|info| info.tx_count))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    use hex;
+    use serde_json::{self, json, Value};
+
+    use chain::{reversed, Error, Header, HeaderSource};
+    use super::{Auth, Bitcoind};
+
+    /// `Authorization` header for user "user", password "pass"
+    const AUTHORIZATION: &'static str = "Basic dXNlcjpwYXNz";
+
+    /// A header for height 1, with made-up contents
+    fn test_header() -> Header {
+        let mut data = vec![0x01, 0x00, 0x00, 0x00];
+        data.extend_from_slice(&[0x11; 32]);
+        data.extend_from_slice(&[0x22; 32]);
+        data.extend_from_slice(&[0x61, 0xbc, 0x66, 0x49, 0xff, 0xff, 0x00, 0x1d, 0x01, 0xe3, 0x62, 0x99]);
+        Header::from_bytes(&data).unwrap()
+    }
+
+    fn test_hash() -> String {
+        hex::encode(reversed(&test_header().block_hash()))
+    }
+
+    /// The result of a verbose `getblockheader` on `test_header`
+    fn test_result(confirmations: u64) -> Value {
+        json!({
+            "hash": test_hash(),
+            "confirmations": confirmations,
+            "height": 1,
+            "version": 1,
+            "versionHex": "00000001",
+            "merkleroot": hex::encode([0x22; 32]),
+            "time": 1231469665,
+            "mediantime": 1231469600,
+            "nonce": 2573394689u32,
+            "bits": "1d00ffff",
+            "difficulty": 1,
+            "nTx": 1,
+            "previousblockhash": hex::encode([0x11; 32])
+        })
+    }
+
+    /// Read an HTTP request, returning its `Authorization` header and body
+    fn read_request(stream: &TcpStream) -> (String, Vec<u8>) {
+        let mut reader = BufReader::new(stream);
+        let mut authorization = String::new();
+        let mut length = 0;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            let mut split = line.splitn(2, ':');
+            let name = split.next().unwrap().to_lowercase();
+            let value = split.next().unwrap_or("").trim().to_owned();
+            if name == "authorization" {
+                authorization = value;
+            } else if name == "content-length" {
+                length = value.parse().unwrap();
+            }
+        }
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).unwrap();
+        (authorization, body)
+    }
+
+    /// Start a fake bitcoind which knows only of the block at height 1,
+    /// whose `getblockheader` result is `result`. Returns its URL and a
+    /// list of the methods called on it.
+    fn spawn_node(result: Value) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let calls = Arc::new(Mutex::new(vec![]));
+        let thread_calls = calls.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => return
+                };
+                let (authorization, body) = read_request(&stream);
+                let request: Value = serde_json::from_slice(&body).unwrap();
+                let method = request["method"].as_str().unwrap().to_owned();
+                thread_calls.lock().unwrap().push(method.clone());
+
+                // bitcoind refuses bad credentials with an empty 401
+                let (status, response) = if authorization != AUTHORIZATION {
+                    ("401 Unauthorized", String::new())
+                } else if method == "getblockhash" && request["params"][0] == 1 {
+                    ("200 OK", json!({ "result": test_hash(), "error": null, "id": "ots-viewer" }).to_string())
+                } else if method == "getblockhash" {
+                    ("500 Internal Server Error", json!({
+                        "result": null,
+                        "error": { "code": -8, "message": "Block height out of range" },
+                        "id": "ots-viewer"
+                    }).to_string())
+                } else if method == "getblockheader" && request["params"][1] == true {
+                    ("200 OK", json!({ "result": result, "error": null, "id": "ots-viewer" }).to_string())
+                } else {
+                    ("500 Internal Server Error", json!({
+                        "result": null,
+                        "error": { "code": -32601, "message": "Method not found" },
+                        "id": "ots-viewer"
+                    }).to_string())
+                };
+                write!(stream, "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                       status, response.len(), response).unwrap();
+            }
+        });
+        (url, calls)
+    }
+
+    fn connect(url: String, pass: &str) -> Bitcoind {
+        let auth = Auth::UserPass("user".to_owned(), pass.to_owned());
+        Bitcoind::new(url, auth, Duration::from_secs(10)).unwrap()
+    }
+
+    #[test]
+    fn success() {
+        let (url, calls) = spawn_node(test_result(100));
+        let bitcoind = connect(url, "pass");
+        assert_eq!(bitcoind.header_at(1).unwrap(), Some(test_header()));
+        assert_eq!(bitcoind.median_time_past(1).unwrap(), Some(1231469600));
+        assert_eq!(bitcoind.tx_count(1).unwrap(), Some(1));
+        // Everything came from a single lookup
+        assert_eq!(*calls.lock().unwrap(), vec!["getblockhash", "getblockheader"]);
+    }
+
+    #[test]
+    fn recent_blocks_not_cached() {
+        let (url, calls) = spawn_node(test_result(1));
+        let bitcoind = connect(url, "pass");
+        assert_eq!(bitcoind.header_at(1).unwrap(), Some(test_header()));
+        assert_eq!(bitcoind.header_at(1).unwrap(), Some(test_header()));
+        assert_eq!(calls.lock().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn unknown_height() {
+        let (url, _) = spawn_node(test_result(100));
+        let bitcoind = connect(url, "pass");
+        assert_eq!(bitcoind.header_at(2).unwrap(), None);
+        assert_eq!(bitcoind.median_time_past(2).unwrap(), None);
+        assert_eq!(bitcoind.tx_count(2).unwrap(), None);
+    }
+
+    #[test]
+    fn auth_failure() {
+        let (url, _) = spawn_node(test_result(100));
+        let bitcoind = connect(url, "wrong");
+        match bitcoind.header_at(1) {
+            Err(Error::Rpc(_)) => {}
+            other => panic!("expected an RPC error, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn mismatched_header() {
+        // The fields do not hash to the block hash asked for
+        let mut result = test_result(100);
+        result["nonce"] = json!(0);
+        let (url, _) = spawn_node(result);
+        let bitcoind = connect(url, "pass");
+        match bitcoind.header_at(1) {
+            Err(Error::Rpc(_)) => {}
+            other => panic!("expected an RPC error, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn missing_tx_count() {
+        let mut result = test_result(100);
+        result.as_object_mut().unwrap().remove("nTx");
+        let (url, _) = spawn_node(result);
+        let bitcoind = connect(url, "pass");
+        assert_eq!(bitcoind.header_at(1).unwrap(), Some(test_header()));
+        assert_eq!(bitcoind.tx_count(1).unwrap(), None);
+    }
+}
@@ -0,0 +1,511 @@
+// OpenTimestamps Viewer
+// Written in 2017 by
+//   Andrew Poelstra <rust-ots@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Cache
+//!
+//! On-disk storage of uploaded timestamps, keyed by document ID, with an
+//! index from document digests to the IDs of their timestamps
+//!
+
+use std::{fmt, fs, io};
+use std::fs::OpenOptions;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use ots::{self, DetachedTimestampFile};
+use ots::hex::Hexed;
+use ots::ser::DigestType;
+use rocket::http::RawStr;
+use rocket::request::FromParam;
+use serde::{Serialize, Serializer};
+
+use canonical;
+
+/// Directory in which timestamps are stored
+pub const CACHE_DIR: &'static str = "cache/";
+
+/// Directory, within a cache, holding links from superseded document IDs
+/// to their replacements
+pub const LINK_DIR: &'static str = "links";
+
+/// Directory, within a cache, holding for each document digest the IDs of
+/// its timestamps
+pub const DIGEST_DIR: &'static str = "digests";
+
+/// Every hash function a timestamp may apply to the document
+pub const DIGEST_TYPES: [DigestType; 3] = [DigestType::Sha1, DigestType::Sha256, DigestType::Ripemd160];
+
+/// File, within a cache, recording the version of `doc_id` it is stored under
+pub const VERSION_FILE: &'static str = "version";
+
+/// Maximum number of links followed when resolving an ID, in case of cycles
+const MAX_LINKS: usize = 32;
+
+/// Errors loading or storing timestamps
+#[derive(Debug)]
+pub enum Error {
+    /// I/O error accessing the cache
+    Io(io::Error),
+    /// Stored timestamp could not be parsed or serialized
+    Ots(ots::error::Error)
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+impl From<ots::error::Error> for Error {
+    fn from(e: ots::error::Error) -> Error {
+        Error::Ots(e)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref e) => write!(f, "{}", e),
+            Error::Ots(ref e) => write!(f, "{}", e)
+        }
+    }
+}
+
+/// Identifier of a cached timestamp: 64 lowercase hex characters, as
+/// produced by `doc_id`. Nothing else can be used to address the cache,
+/// so it can never be asked to open an arbitrary path.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct DocId(String);
+
+impl DocId {
+    /// Parse a document ID, accepting only 64 hex characters
+    pub fn from_hex(s: &str) -> Option<DocId> {
+        if s.len() == 64 && s.bytes().all(|b| (b as char).is_digit(16)) {
+            Some(DocId(s.to_ascii_lowercase()))
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for DocId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Serialize for DocId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+impl<'a> FromParam<'a> for DocId {
+    type Error = &'a RawStr;
+
+    fn from_param(param: &'a RawStr) -> Result<DocId, &'a RawStr> {
+        DocId::from_hex(param.as_str()).ok_or(param)
+    }
+}
+
+/// Compute a unique filename for this timestamp, from the hash of its
+/// canonical encoding
+pub fn doc_id(dtf: &DetachedTimestampFile) -> DocId {
+    DocId(format!("{}", Hexed(&canonical::hash(dtf))))
+}
+
+/// A cache of timestamps stored below some directory
+pub struct Cache {
+    root: PathBuf
+}
+
+impl Cache {
+    /// A cache stored below `root`, which need not exist yet
+    pub fn new<P: AsRef<Path>>(root: P) -> Cache {
+        Cache {
+            root: root.as_ref().to_path_buf()
+        }
+    }
+
+    /// Path of the file holding the given document
+    pub fn path(&self, id: &DocId) -> PathBuf {
+        self.root.join(&id.0)
+    }
+
+    /// Load a timestamp from the cache
+    pub fn load(&self, id: &DocId) -> Result<DetachedTimestampFile, Error> {
+        let fh = fs::File::open(self.path(id))?;
+        Ok(DetachedTimestampFile::from_reader(fh)?)
+    }
+
+    /// Store a timestamp in the cache, returning its document ID
+    pub fn store(&self, dtf: &DetachedTimestampFile) -> Result<DocId, Error> {
+        let id = doc_id(dtf);
+        let fh = fs::File::create(self.path(&id))?;
+        dtf.to_writer(fh)?;
+        self.index_digest(dtf, &id)?;
+        Ok(id)
+    }
+
+    /// Path of the index file listing the timestamps of a document digest
+    fn digest_path(&self, digest_type: &DigestType, digest: &[u8]) -> PathBuf {
+        let name = format!("{}-{}", digest_type.to_string().to_lowercase(), Hexed(digest));
+        self.root.join(DIGEST_DIR).join(name)
+    }
+
+    /// Record that `id` is a timestamp of the document digest of `dtf`
+    fn index_digest(&self, dtf: &DetachedTimestampFile, id: &DocId) -> Result<(), Error> {
+        if self.find_by_digest(&dtf.digest_type, &dtf.timestamp.start_digest)?.contains(id) {
+            return Ok(());
+        }
+        fs::create_dir_all(self.root.join(DIGEST_DIR))?;
+        let mut fh = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.digest_path(&dtf.digest_type, &dtf.timestamp.start_digest))?;
+        // A single short write, so concurrent appends do not interleave
+        fh.write_all(format!("{}\n", id).as_bytes())?;
+        Ok(())
+    }
+
+    /// The IDs of every stored timestamp of a document digest, as stored,
+    /// i.e. without following links
+    pub fn find_by_digest(&self, digest_type: &DigestType, digest: &[u8]) -> Result<Vec<DocId>, Error> {
+        let mut list = String::new();
+        match fs::File::open(self.digest_path(digest_type, digest)) {
+            Ok(mut fh) => {
+                fh.read_to_string(&mut list)?;
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(Error::Io(e))
+        }
+        Ok(list.lines().filter_map(|line| DocId::from_hex(line.trim())).collect())
+    }
+
+    /// The IDs of every timestamp stored in the cache, linked or not
+    pub fn ids(&self) -> Result<Vec<DocId>, Error> {
+        let mut ids = vec![];
+        for entry in fs::read_dir(&self.root)? {
+            let entry = entry?;
+            if !entry.path().is_file() {
+                continue;
+            }
+            if let Some(id) = entry.file_name().to_str().and_then(DocId::from_hex) {
+                ids.push(id);
+            }
+        }
+        Ok(ids)
+    }
+
+    /// Build the digest index for a cache written before there was one,
+    /// returning the number of timestamps indexed. Does nothing if the index
+    /// already exists.
+    pub fn migrate_digest_index(&self) -> Result<usize, Error> {
+        if self.root.join(DIGEST_DIR).is_dir() {
+            return Ok(0);
+        }
+        fs::create_dir_all(self.root.join(DIGEST_DIR))?;
+        let mut count = 0;
+        for id in self.ids()? {
+            match self.load(&id) {
+                Ok(dtf) => {
+                    self.index_digest(&dtf, &id)?;
+                    count += 1;
+                }
+                Err(e) => println!("Skipping unreadable cached timestamp {}: {}", id, e)
+            }
+        }
+        Ok(count)
+    }
+
+    /// Move timestamps stored under an earlier version of `doc_id` to their
+    /// current IDs, returning the number moved. The old IDs are linked to the
+    /// new ones so that they stay resolvable. Does nothing if the cache is
+    /// already stored under the current version.
+    pub fn migrate_doc_ids(&self) -> Result<usize, Error> {
+        let mut version = String::new();
+        match fs::File::open(self.root.join(VERSION_FILE)) {
+            Ok(mut fh) => {
+                fh.read_to_string(&mut version)?;
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(Error::Io(e))
+        }
+        if version.trim() == canonical::VERSION.to_string() {
+            return Ok(0);
+        }
+
+        fs::create_dir_all(&self.root)?;
+        let mut count = 0;
+        // The IDs are all collected first, since migrating adds files
+        for id in self.ids()? {
+            let dtf = match self.load(&id) {
+                Ok(dtf) => dtf,
+                Err(e) => {
+                    println!("Skipping unreadable cached timestamp {}: {}", id, e);
+                    continue;
+                }
+            };
+            let new_id = doc_id(&dtf);
+            if new_id == id {
+                continue;
+            }
+            self.store(&dtf)?;
+            if self.is_linked(&id) {
+                // Already superseded: leave the old link alone and point the
+                // new ID the same way, so it is not upgraded a second time
+                self.link(&new_id, &self.resolve(&id))?;
+            } else {
+                self.link(&id, &new_id)?;
+            }
+            fs::remove_file(self.path(&id))?;
+            count += 1;
+        }
+
+        let mut fh = fs::File::create(self.root.join(VERSION_FILE))?;
+        fh.write_all(canonical::VERSION.to_string().as_bytes())?;
+        Ok(count)
+    }
+
+    /// Record that the document `old_id` has been superseded by `new_id`
+    pub fn link(&self, old_id: &DocId, new_id: &DocId) -> Result<(), Error> {
+        if old_id == new_id {
+            return Ok(());
+        }
+        fs::create_dir_all(self.root.join(LINK_DIR))?;
+        let mut fh = fs::File::create(self.root.join(LINK_DIR).join(&old_id.0))?;
+        fh.write_all(new_id.0.as_bytes())?;
+        Ok(())
+    }
+
+    /// Whether the document has been superseded by another
+    pub fn is_linked(&self, id: &DocId) -> bool {
+        self.root.join(LINK_DIR).join(&id.0).is_file()
+    }
+
+    /// Follow links from a document ID to the best version of the document
+    pub fn resolve(&self, id: &DocId) -> DocId {
+        let mut id = id.clone();
+        for _ in 0..MAX_LINKS {
+            let mut new_id = String::new();
+            match fs::File::open(self.root.join(LINK_DIR).join(&id.0)) {
+                Ok(mut fh) => {
+                    if fh.read_to_string(&mut new_id).is_err() {
+                        break;
+                    }
+                }
+                Err(_) => break
+            }
+            match DocId::from_hex(new_id.trim()) {
+                Some(new_id) => id = new_id,
+                None => break
+            }
+        }
+        id
+    }
+}
+
+/// The cache used by the server, in `CACHE_DIR`
+fn server_cache() -> Cache {
+    Cache::new(CACHE_DIR)
+}
+
+/// Path of the file holding the given document
+pub fn path(id: &DocId) -> PathBuf {
+    server_cache().path(id)
+}
+
+/// Load a timestamp from the cache
+pub fn load(id: &DocId) -> Result<DetachedTimestampFile, Error> {
+    server_cache().load(id)
+}
+
+/// Store a timestamp in the cache, returning its document ID
+pub fn store(dtf: &DetachedTimestampFile) -> Result<DocId, Error> {
+    server_cache().store(dtf)
+}
+
+/// The IDs of every stored timestamp of a document digest, as stored,
+/// i.e. without following links
+pub fn find_by_digest(digest_type: &DigestType, digest: &[u8]) -> Result<Vec<DocId>, Error> {
+    server_cache().find_by_digest(digest_type, digest)
+}
+
+/// Build the digest index for a cache written before there was one
+pub fn migrate_digest_index() -> Result<usize, Error> {
+    server_cache().migrate_digest_index()
+}
+
+/// Move timestamps stored under an earlier version of `doc_id` to their
+/// current IDs
+pub fn migrate_doc_ids() -> Result<usize, Error> {
+    server_cache().migrate_doc_ids()
+}
+
+/// Record that the document `old_id` has been superseded by `new_id`
+pub fn link(old_id: &DocId, new_id: &DocId) -> Result<(), Error> {
+    server_cache().link(old_id, new_id)
+}
+
+/// Whether the document has been superseded by another
+pub fn is_linked(id: &DocId) -> bool {
+    server_cache().is_linked(id)
+}
+
+/// Follow links from a document ID to the best version of the document
+pub fn resolve(id: &DocId) -> DocId {
+    server_cache().resolve(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+    use std::path::PathBuf;
+    use std::io::Write;
+
+    use ots::DetachedTimestampFile;
+    use ots::attestation::Attestation;
+    use ots::op::Op;
+    use ots::ser::DigestType;
+
+    use canonical;
+    use testutil::{attest, file, op};
+    use super::*;
+
+    fn timestamp(attestation: Attestation) -> DetachedTimestampFile {
+        file(op(Op::Append(vec![0x01]), attest(attestation)))
+    }
+
+    /// A fresh directory for a cache, unique to the calling test
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("ots-viewer-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Write a timestamp under an ID of the kind an older `doc_id` made
+    fn store_as(cache: &Cache, dtf: &DetachedTimestampFile, id: &DocId) {
+        let fh = fs::File::create(cache.path(id)).unwrap();
+        dtf.to_writer(fh).unwrap();
+    }
+
+    fn old_id(n: u8) -> DocId {
+        DocId::from_hex(&format!("{:064x}", n)).unwrap()
+    }
+
+    #[test]
+    fn doc_id_format() {
+        let id = doc_id(&timestamp(Attestation::Bitcoin { height: 100 }));
+        assert_eq!(DocId::from_hex(&id.to_string()), Some(id.clone()));
+        assert!(id != doc_id(&timestamp(Attestation::Bitcoin { height: 101 })));
+    }
+
+    #[test]
+    fn digest_index() {
+        let dir = temp_dir("digest-index");
+        let cache = Cache::new(&dir);
+
+        // Two timestamps of the same document, stored twice over
+        let first = timestamp(Attestation::Bitcoin { height: 100 });
+        let second = timestamp(Attestation::Pending { uri: "https://a.pool.opentimestamps.org".to_owned() });
+        let first_id = cache.store(&first).unwrap();
+        let second_id = cache.store(&second).unwrap();
+        assert_eq!(cache.store(&first).unwrap(), first_id);
+
+        let digest = &first.timestamp.start_digest;
+        assert_eq!(cache.find_by_digest(&DigestType::Sha256, digest).unwrap(), vec![first_id, second_id]);
+        // The digest type is part of the key
+        assert!(cache.find_by_digest(&DigestType::Sha1, &digest[..20]).unwrap().is_empty());
+        assert!(cache.find_by_digest(&DigestType::Sha256, &[0xbb; 32]).unwrap().is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn digest_index_migration() {
+        let dir = temp_dir("digest-index-migration");
+        let cache = Cache::new(&dir);
+
+        // A cache written before there was an index
+        let first = timestamp(Attestation::Bitcoin { height: 100 });
+        let second = timestamp(Attestation::Bitcoin { height: 200 });
+        store_as(&cache, &first, &doc_id(&first));
+        store_as(&cache, &second, &doc_id(&second));
+        fs::File::create(cache.path(&old_id(1))).unwrap().write_all(b"junk").unwrap();
+        let digest = &first.timestamp.start_digest;
+        assert!(cache.find_by_digest(&DigestType::Sha256, digest).unwrap().is_empty());
+
+        assert_eq!(cache.migrate_digest_index().unwrap(), 2);
+        let mut found = cache.find_by_digest(&DigestType::Sha256, digest).unwrap();
+        found.sort_by(|a, b| a.to_string().cmp(&b.to_string()));
+        let mut expected = vec![doc_id(&first), doc_id(&second)];
+        expected.sort_by(|a, b| a.to_string().cmp(&b.to_string()));
+        assert_eq!(found, expected);
+
+        // Once the index exists, it is left alone
+        store_as(&cache, &timestamp(Attestation::Bitcoin { height: 300 }), &old_id(2));
+        assert_eq!(cache.migrate_digest_index().unwrap(), 0);
+        assert_eq!(cache.find_by_digest(&DigestType::Sha256, digest).unwrap().len(), 2);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn migration() {
+        let dir = temp_dir("migration");
+        let cache = Cache::new(&dir);
+
+        // A timestamp stored under an old ID
+        let plain = timestamp(Attestation::Bitcoin { height: 100 });
+        store_as(&cache, &plain, &old_id(1));
+        // A pending timestamp, since upgraded, both under old IDs
+        let pending = timestamp(Attestation::Pending { uri: "https://a.pool.opentimestamps.org".to_owned() });
+        let upgraded = timestamp(Attestation::Bitcoin { height: 200 });
+        store_as(&cache, &pending, &old_id(2));
+        store_as(&cache, &upgraded, &old_id(3));
+        cache.link(&old_id(2), &old_id(3)).unwrap();
+        // A timestamp already stored under its current ID
+        let current = timestamp(Attestation::Bitcoin { height: 300 });
+        let current_id = cache.store(&current).unwrap();
+        // Something which is not a timestamp at all
+        fs::File::create(cache.path(&old_id(4))).unwrap().write_all(b"junk").unwrap();
+
+        assert_eq!(cache.migrate_doc_ids().unwrap(), 3);
+
+        // Old IDs are gone from the cache but resolve to the new ones
+        assert!(!cache.path(&old_id(1)).exists());
+        assert_eq!(cache.resolve(&old_id(1)), doc_id(&plain));
+        assert_eq!(cache.load(&doc_id(&plain)).unwrap().timestamp.start_digest, vec![0xaa; 32]);
+        // The upgrade link survives, both from the old ID and the new one
+        assert_eq!(cache.resolve(&old_id(2)), doc_id(&upgraded));
+        assert_eq!(cache.resolve(&doc_id(&pending)), doc_id(&upgraded));
+        assert!(cache.is_linked(&doc_id(&pending)));
+        assert!(!cache.is_linked(&doc_id(&upgraded)));
+        // Current and unreadable files are left alone
+        assert_eq!(cache.resolve(&current_id), current_id);
+        assert!(cache.path(&current_id).exists());
+        assert!(cache.path(&old_id(4)).exists());
+
+        // Migrating again does nothing
+        let mut version = String::new();
+        fs::File::open(dir.join(VERSION_FILE)).unwrap().read_to_string(&mut version).unwrap();
+        assert_eq!(version, canonical::VERSION.to_string());
+        store_as(&cache, &plain, &old_id(5));
+        assert_eq!(cache.migrate_doc_ids().unwrap(), 0);
+        assert!(cache.path(&old_id(5)).exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,515 @@
+// OpenTimestamps Viewer
+// Written in 2017 by
+//   Andrew Poelstra <rust-ots@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Calendar
+//!
+//! Upgrading pending attestations by querying calendar servers
+//!
+
+use std::io::Read;
+use std::mem;
+use std::time::Duration;
+
+use ots::DetachedTimestampFile;
+use ots::attestation::Attestation;
+use ots::hex::Hexed;
+use ots::ser::Deserializer;
+use ots::timestamp::{Step, StepData, Timestamp};
+use reqwest;
+
+use cache::{self, Cache, DocId};
+use tree::{self, Limits};
+
+/// Largest response we will accept from a calendar
+const RESPONSE_LIMIT: u64 = 65536;
+
+/// Calendars trusted by default, matching the whitelist of the reference
+/// client. A leading `*.` matches any subdomain.
+pub const DEFAULT_WHITELIST: &'static [&'static str] = &[
+    "https://*.calendar.opentimestamps.org",
+    "https://*.calendar.eternitywall.com",
+    "https://*.calendar.catallaxy.com"
+];
+
+/// Result of trying to upgrade a single pending attestation
+#[derive(Clone, PartialEq, Eq, Debug, Serialize)]
+#[serde(tag = "result", content = "detail", rename_all = "snake_case")]
+pub enum Outcome {
+    /// The attestation was replaced by the calendar's response
+    Upgraded,
+    /// The calendar does not have a complete timestamp yet
+    Pending,
+    /// The calendar is not on our whitelist, so was not contacted
+    NotWhitelisted,
+    /// Something went wrong talking to the calendar
+    Failed(String)
+}
+
+/// Result of trying to upgrade a pending attestation from some calendar
+#[derive(Clone, PartialEq, Eq, Debug, Serialize)]
+pub struct Attempt {
+    /// URI of the calendar
+    pub uri: String,
+    /// What happened
+    pub outcome: Outcome
+}
+
+/// Result of trying to upgrade a cached timestamp
+#[derive(Clone, PartialEq, Eq, Debug, Serialize)]
+pub struct UpgradeReport {
+    /// ID of the upgraded timestamp, or the original if nothing changed
+    pub id: DocId,
+    /// Whether any attestation was upgraded
+    pub upgraded: bool,
+    /// Every calendar that was (or was not) contacted
+    pub attempts: Vec<Attempt>
+}
+
+/// A client for calendar servers
+pub struct Calendars {
+    client: reqwest::Client,
+    whitelist: Vec<String>,
+    /// Limits which upgraded timestamps must stay within
+    limits: Limits
+}
+
+impl Calendars {
+    /// Create a new client which will contact only whitelisted calendars,
+    /// giving up on each after `timeout`, and accept only upgrades which
+    /// leave timestamps within `limits`
+    pub fn new(whitelist: Vec<String>, timeout: Duration, limits: Limits) -> Result<Calendars, reqwest::Error> {
+        let client = reqwest::Client::builder()
+            .timeout(timeout)
+            .build()?;
+        Ok(Calendars {
+            client: client,
+            whitelist: whitelist,
+            limits: limits
+        })
+    }
+
+    /// Whether the given calendar URI may be contacted. URIs come from
+    /// uploaded files, so must not be trusted to point anywhere sensible.
+    pub fn is_whitelisted(&self, uri: &str) -> bool {
+        self.whitelist.iter().any(|pattern| uri_matches(pattern, uri))
+    }
+
+    /// Ask a calendar for the complete timestamp of `commitment`
+    fn fetch(&self, uri: &str, commitment: &[u8]) -> Result<Option<Timestamp>, String> {
+        let url = format!("{}/timestamp/{}", uri.trim_end_matches('/'), Hexed(commitment));
+        let response = self.client.get(&url)
+            .header(reqwest::header::ACCEPT, "application/vnd.opentimestamps.v1")
+            .send()
+            .map_err(|e| e.to_string())?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !response.status().is_success() {
+            return Err(format!("HTTP {}", response.status()));
+        }
+        let mut deser = Deserializer::new(response.take(RESPONSE_LIMIT));
+        match Timestamp::deserialize(&mut deser, commitment.to_vec()) {
+            Ok(timestamp) => Ok(Some(timestamp)),
+            Err(e) => Err(format!("bad timestamp: {}", e))
+        }
+    }
+
+    /// Replace pending attestations under `first_step`, whose input is
+    /// `start_digest`, with whatever the calendars return. Returns each
+    /// replaced step, with the index of the step taken from each step
+    /// before it to reach it.
+    fn upgrade_steps(&self, first_step: &mut Step, start_digest: &[u8], attempts: &mut Vec<Attempt>) -> Vec<(Vec<usize>, Step)> {
+        let mut replaced = vec![];
+        // Each entry is a step, its input and its path from the root
+        let mut stack = vec![(first_step, start_digest.to_vec(), vec![])];
+        while let Some((step, input, path)) = stack.pop() {
+            let uri = match step.data {
+                StepData::Fork => {
+                    // Push in reverse so that the first branch is upgraded first
+                    for (n, next) in step.next.iter_mut().enumerate().rev() {
+                        let mut next_path = path.clone();
+                        next_path.push(n);
+                        stack.push((next, input.clone(), next_path));
+                    }
+                    continue;
+                }
+                StepData::Op(_) => {
+                    let output = step.output.clone();
+                    if let Some(next) = step.next.first_mut() {
+                        let mut next_path = path.clone();
+                        next_path.push(0);
+                        stack.push((next, output, next_path));
+                    }
+                    continue;
+                }
+                StepData::Attestation(Attestation::Pending { ref uri }) => uri.clone(),
+                StepData::Attestation(_) => continue
+            };
+
+            let outcome = if !self.is_whitelisted(&uri) {
+                Outcome::NotWhitelisted
+            } else {
+                match self.fetch(&uri, &input) {
+                    Ok(Some(timestamp)) => {
+                        // The response replaces a step `path.len()` deep, so
+                        // must not take the tree past our depth limit
+                        let limits = Limits {
+                            max_depth: self.limits.max_depth.saturating_sub(path.len()),
+                            max_steps: self.limits.max_steps
+                        };
+                        match tree::check(&timestamp.first_step, &input, &limits) {
+                            Ok(()) => {
+                                replaced.push((path, mem::replace(step, timestamp.first_step)));
+                                Outcome::Upgraded
+                            }
+                            Err(e) => Outcome::Failed(format!("bad timestamp: {}", e))
+                        }
+                    }
+                    Ok(None) => Outcome::Pending,
+                    Err(e) => Outcome::Failed(e)
+                }
+            };
+            attempts.push(Attempt {
+                uri: uri,
+                outcome: outcome
+            });
+        }
+        replaced
+    }
+
+    /// Try to upgrade every pending attestation in the timestamp, splicing
+    /// in whatever the calendars return. Returns a record of every calendar
+    /// that was (or was not) contacted.
+    pub fn upgrade(&self, dtf: &mut DetachedTimestampFile) -> Vec<Attempt> {
+        let mut attempts = vec![];
+        let start_digest = dtf.timestamp.start_digest.clone();
+        let replaced = self.upgrade_steps(&mut dtf.timestamp.first_step, &start_digest, &mut attempts);
+
+        // Each response was checked on its own, but together they may still
+        // take the tree past our limits, in which case none are kept
+        if let Err(e) = tree::check(&dtf.timestamp.first_step, &start_digest, &self.limits) {
+            for (path, original) in replaced {
+                let mut step = &mut dtf.timestamp.first_step;
+                for n in path {
+                    step = &mut step.next[n];
+                }
+                *step = original;
+            }
+            for attempt in &mut attempts {
+                if attempt.outcome == Outcome::Upgraded {
+                    attempt.outcome = Outcome::Failed(format!("upgraded timestamp too large: {}", e));
+                }
+            }
+        }
+        attempts
+    }
+
+    /// Try to upgrade a cached timestamp, storing the result under its new
+    /// ID (and linking the old ID to it) if any of its pending attestations
+    /// could be upgraded
+    pub fn upgrade_cached(&self, cache: &Cache, id: &DocId) -> Result<UpgradeReport, cache::Error> {
+        let old_id = cache.resolve(id);
+        let mut dtf = cache.load(&old_id)?;
+        let attempts = self.upgrade(&mut dtf);
+        let upgraded = attempts.iter().any(|a| a.outcome == Outcome::Upgraded);
+        let id = if upgraded {
+            let new_id = cache.store(&dtf)?;
+            cache.link(&old_id, &new_id)?;
+            new_id
+        } else {
+            cache::doc_id(&dtf)
+        };
+        Ok(UpgradeReport {
+            id: id,
+            upgraded: upgraded,
+            attempts: attempts
+        })
+    }
+}
+
+/// Whether any attestation in the tree satisfies `pred`
+fn any_attestation<F: Fn(&Attestation) -> bool>(first_step: &Step, pred: F) -> bool {
+    let mut stack = vec![first_step];
+    while let Some(step) = stack.pop() {
+        if let StepData::Attestation(ref attest) = step.data {
+            if pred(attest) {
+                return true;
+            }
+        }
+        stack.extend(step.next.iter());
+    }
+    false
+}
+
+/// Whether any path through the timestamp ends in a pending attestation
+pub fn has_pending(first_step: &Step) -> bool {
+    any_attestation(first_step, |attest| match *attest {
+        Attestation::Pending { .. } => true,
+        _ => false
+    })
+}
+
+/// Whether any path through the timestamp ends in a Bitcoin attestation
+pub fn has_bitcoin(first_step: &Step) -> bool {
+    any_attestation(first_step, |attest| match *attest {
+        Attestation::Bitcoin { .. } => true,
+        _ => false
+    })
+}
+
+/// Whether every path through the timestamp ends in a pending attestation
+pub fn all_pending(first_step: &Step) -> bool {
+    !any_attestation(first_step, |attest| match *attest {
+        Attestation::Pending { .. } => false,
+        _ => true
+    })
+}
+
+/// Whether `uri` matches a whitelist entry, which is either an exact URI
+/// or a scheme followed by `*.domain`, matching any subdomain of `domain`
+fn uri_matches(pattern: &str, uri: &str) -> bool {
+    let pattern = pattern.trim_end_matches('/');
+    let uri = uri.trim_end_matches('/');
+    match pattern.find("://*.") {
+        Some(idx) => {
+            let scheme = &pattern[..idx + 3];
+            let domain = &pattern[idx + 4..];
+            if !uri.starts_with(scheme) {
+                return false;
+            }
+            let host = uri[scheme.len()..].split('/').next().unwrap_or("");
+            // Reject userinfo and ports, which could be used to sneak a
+            // whitelisted-looking suffix into a URI pointing elsewhere
+            host.len() > domain.len() &&
+            host.ends_with(domain) &&
+            host.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+        }
+        None => pattern == uri
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Duration;
+
+    use ots::DetachedTimestampFile;
+    use ots::attestation::Attestation;
+    use ots::hex::Hexed;
+    use ots::op::Op;
+    use ots::timestamp::StepData;
+
+    use testutil::{file, fork, pending, serialized, DIGEST};
+    use tree::Limits;
+
+    use super::{uri_matches, Calendars, Outcome, DEFAULT_WHITELIST};
+
+    #[test]
+    fn exact_uris() {
+        assert!(uri_matches("https://alice.btc.calendar.opentimestamps.org", "https://alice.btc.calendar.opentimestamps.org"));
+        assert!(uri_matches("https://alice.btc.calendar.opentimestamps.org/", "https://alice.btc.calendar.opentimestamps.org"));
+        assert!(uri_matches("https://alice.btc.calendar.opentimestamps.org", "https://alice.btc.calendar.opentimestamps.org/"));
+        assert!(!uri_matches("https://alice.btc.calendar.opentimestamps.org", "http://alice.btc.calendar.opentimestamps.org"));
+        assert!(!uri_matches("https://alice.btc.calendar.opentimestamps.org", "https://bob.btc.calendar.opentimestamps.org"));
+        assert!(!uri_matches("https://alice.btc.calendar.opentimestamps.org", "https://alice.btc.calendar.opentimestamps.org.evil.com"));
+    }
+
+    #[test]
+    fn wildcard_uris() {
+        let pattern = "https://*.calendar.opentimestamps.org";
+        assert!(uri_matches(pattern, "https://alice.btc.calendar.opentimestamps.org"));
+        assert!(uri_matches(pattern, "https://a.calendar.opentimestamps.org/"));
+        // The wildcard matches subdomains, not the domain itself
+        assert!(!uri_matches(pattern, "https://calendar.opentimestamps.org"));
+        assert!(!uri_matches(pattern, "https://.calendar.opentimestamps.org"));
+        assert!(!uri_matches(pattern, "https://evilcalendar.opentimestamps.org"));
+        assert!(!uri_matches(pattern, "http://a.calendar.opentimestamps.org"));
+        assert!(!uri_matches(pattern, "https://a.calendar.opentimestamps.org.evil.com"));
+    }
+
+    #[test]
+    fn wildcard_smuggling() {
+        let pattern = "https://*.calendar.opentimestamps.org";
+        // Userinfo, ports, queries and fragments are all refused
+        assert!(!uri_matches(pattern, "https://evil.com@a.calendar.opentimestamps.org"));
+        assert!(!uri_matches(pattern, "https://a.calendar.opentimestamps.org@evil.com"));
+        assert!(!uri_matches(pattern, "https://a.calendar.opentimestamps.org:8080"));
+        assert!(!uri_matches(pattern, "https://evil.com?.calendar.opentimestamps.org"));
+        assert!(!uri_matches(pattern, "https://evil.com#.calendar.opentimestamps.org"));
+        assert!(!uri_matches(pattern, "https://evil.com\\.calendar.opentimestamps.org"));
+        assert!(!uri_matches(pattern, "https://a.CALENDAR.opentimestamps.org"));
+        assert!(!uri_matches(pattern, ""));
+    }
+
+    #[test]
+    fn default_whitelist() {
+        let calendars = Calendars::new(DEFAULT_WHITELIST.iter().map(|s| s.to_string()).collect(), Duration::from_secs(1), Limits::default()).unwrap();
+        assert!(calendars.is_whitelisted("https://alice.btc.calendar.opentimestamps.org"));
+        assert!(calendars.is_whitelisted("https://finney.calendar.eternitywall.com"));
+        assert!(!calendars.is_whitelisted("https://ots.btc.catallaxy.com"));
+        assert!(calendars.is_whitelisted("https://btc.calendar.catallaxy.com"));
+        assert!(!calendars.is_whitelisted("http://127.0.0.1:14788"));
+    }
+
+    /// Start a fake calendar which serves `body` for the timestamp of
+    /// `commitment` with the given HTTP status. Returns its URI.
+    fn spawn_calendar(commitment: Vec<u8>, status: &'static str, body: Vec<u8>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let uri = format!("http://{}", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => return
+                };
+                let mut request_line = String::new();
+                BufReader::new(&stream).read_line(&mut request_line).unwrap();
+                let expected = format!("GET /timestamp/{} ", Hexed(&commitment));
+                let (status, body) = if request_line.starts_with(&expected) {
+                    (status, body.clone())
+                } else {
+                    ("404 Not Found", vec![])
+                };
+                write!(stream, "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", status, body.len()).unwrap();
+                stream.write_all(&body).unwrap();
+            }
+        });
+        uri
+    }
+
+    /// A serialized timestamp consisting of a SHA256 operation followed
+    /// by a Bitcoin attestation at height 100
+    fn complete_timestamp() -> Vec<u8> {
+        vec![0x08, 0x00, 0x05, 0x88, 0x96, 0x0d, 0x73, 0xd7, 0x19, 0x01, 0x01, 0x64]
+    }
+
+    /// A timestamp of `DIGEST` with pending attestations from each of `uris`
+    fn pending_timestamp(uris: &[&str]) -> DetachedTimestampFile {
+        file(fork(uris.iter().map(|uri| pending(uri)).collect()))
+    }
+
+    #[test]
+    fn upgrade() {
+        let uri = spawn_calendar(DIGEST.to_vec(), "200 OK", complete_timestamp());
+        let calendars = Calendars::new(vec![uri.clone()], Duration::from_secs(10), Limits::default()).unwrap();
+        let mut dtf = pending_timestamp(&[&uri, "https://evil.example.com"]);
+
+        let attempts = calendars.upgrade(&mut dtf);
+        assert_eq!(attempts.len(), 2);
+        assert_eq!(attempts[0].uri, uri);
+        assert_eq!(attempts[0].outcome, Outcome::Upgraded);
+        assert_eq!(attempts[1].uri, "https://evil.example.com");
+        assert_eq!(attempts[1].outcome, Outcome::NotWhitelisted);
+
+        let upgraded = &dtf.timestamp.first_step.next[0];
+        assert_eq!(upgraded.data, StepData::Op(Op::Sha256));
+        assert_eq!(upgraded.next[0].data, StepData::Attestation(Attestation::Bitcoin { height: 100 }));
+        assert_eq!(upgraded.next[0].output, upgraded.output);
+        // The calendar we did not ask is untouched
+        let untouched = pending("https://evil.example.com");
+        assert_eq!(dtf.timestamp.first_step.next[1].data, untouched.data);
+    }
+
+    #[test]
+    fn still_pending() {
+        let uri = spawn_calendar(vec![0xbb; 32], "200 OK", complete_timestamp());
+        let calendars = Calendars::new(vec![uri.clone()], Duration::from_secs(10), Limits::default()).unwrap();
+        let mut dtf = pending_timestamp(&[&uri]);
+        let original = serialized(&dtf);
+
+        let attempts = calendars.upgrade(&mut dtf);
+        assert_eq!(attempts.len(), 1);
+        assert_eq!(attempts[0].outcome, Outcome::Pending);
+        assert_eq!(serialized(&dtf), original);
+    }
+
+    #[test]
+    fn calendar_failure() {
+        let uri = spawn_calendar(DIGEST.to_vec(), "500 Internal Server Error", vec![]);
+        let calendars = Calendars::new(vec![uri.clone()], Duration::from_secs(10), Limits::default()).unwrap();
+        let mut dtf = pending_timestamp(&[&uri]);
+        let original = serialized(&dtf);
+
+        let attempts = calendars.upgrade(&mut dtf);
+        assert_eq!(attempts[0].outcome, Outcome::Failed("HTTP 500 Internal Server Error".to_owned()));
+        assert_eq!(serialized(&dtf), original);
+    }
+
+    #[test]
+    fn malformed_response() {
+        let uri = spawn_calendar(DIGEST.to_vec(), "200 OK", vec![0x08, 0x00]);
+        let calendars = Calendars::new(vec![uri.clone()], Duration::from_secs(10), Limits::default()).unwrap();
+        let mut dtf = pending_timestamp(&[&uri]);
+        let original = serialized(&dtf);
+
+        let attempts = calendars.upgrade(&mut dtf);
+        match attempts[0].outcome {
+            Outcome::Failed(_) => {}
+            ref other => panic!("expected a failure, got {:?}", other)
+        }
+        assert_eq!(serialized(&dtf), original);
+    }
+
+    #[test]
+    fn oversized_response() {
+        let uri = spawn_calendar(DIGEST.to_vec(), "200 OK", complete_timestamp());
+        // The pending attestation is below a fork, and the response is two
+        // steps deep, so the upgraded tree is three steps deep
+        let limits = Limits { max_depth: 2, ..Limits::default() };
+        let calendars = Calendars::new(vec![uri.clone()], Duration::from_secs(10), limits).unwrap();
+        let mut dtf = pending_timestamp(&[&uri]);
+        let original = serialized(&dtf);
+
+        let attempts = calendars.upgrade(&mut dtf);
+        match attempts[0].outcome {
+            Outcome::Failed(_) => {}
+            ref other => panic!("expected a failure, got {:?}", other)
+        }
+        assert_eq!(serialized(&dtf), original);
+
+        let limits = Limits { max_depth: 3, ..Limits::default() };
+        let calendars = Calendars::new(vec![uri.clone()], Duration::from_secs(10), limits).unwrap();
+        let attempts = calendars.upgrade(&mut dtf);
+        assert_eq!(attempts[0].outcome, Outcome::Upgraded);
+    }
+
+    #[test]
+    fn oversized_upgrade() {
+        let first = spawn_calendar(DIGEST.to_vec(), "200 OK", complete_timestamp());
+        let second = spawn_calendar(DIGEST.to_vec(), "200 OK", complete_timestamp());
+        // Each response adds a step, which fits, but both together do not
+        let limits = Limits { max_steps: 4, ..Limits::default() };
+        let calendars = Calendars::new(vec![first.clone(), second.clone()], Duration::from_secs(10), limits).unwrap();
+        let mut dtf = pending_timestamp(&[&first, &second]);
+        let original = serialized(&dtf);
+
+        let attempts = calendars.upgrade(&mut dtf);
+        assert_eq!(attempts.len(), 2);
+        for attempt in &attempts {
+            match attempt.outcome {
+                Outcome::Failed(_) => {}
+                ref other => panic!("expected a failure, got {:?}", other)
+            }
+        }
+        assert_eq!(serialized(&dtf), original);
+
+        let limits = Limits { max_steps: 5, ..Limits::default() };
+        let calendars = Calendars::new(vec![first.clone(), second.clone()], Duration::from_secs(10), limits).unwrap();
+        let attempts = calendars.upgrade(&mut dtf);
+        assert_eq!(attempts[0].outcome, Outcome::Upgraded);
+        assert_eq!(attempts[1].outcome, Outcome::Upgraded);
+    }
+}
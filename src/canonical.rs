@@ -0,0 +1,259 @@
+// OpenTimestamps Viewer
+// Written in 2017 by
+//   Andrew Poelstra <rust-ots@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Canonical Encoding
+//!
+//! The versioned encoding of whole timestamps from which document IDs are
+//! derived. It covers every op, fork and attestation, but not the order in
+//! which the branches of a fork happen to be serialized.
+//!
+
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+use ots::DetachedTimestampFile;
+use ots::attestation::Attestation;
+use ots::op::Op;
+use ots::ser::DigestType;
+use ots::timestamp::{Step, StepData};
+
+use tree;
+
+/// Version of the encoding. Any change to the encoding must bump this, so
+/// that caches keyed by the old hashes know to migrate.
+pub const VERSION: u8 = 1;
+
+/// The SHA256 of the canonical encoding of a timestamp: the encoding
+/// version, digest type and start digest, followed by the hash of the first
+/// step. Each step is hashed over its op or attestation and the sorted
+/// hashes of the steps following it.
+pub fn hash(dtf: &DetachedTimestampFile) -> [u8; 32] {
+    // Flatten the tree in depth-first order, noting each step's parent,
+    // using an explicit stack rather than recursion since the tree may be
+    // arbitrarily deep
+    let mut steps: Vec<(&Step, Option<usize>)> = vec![];
+    let mut stack: Vec<(&Step, Option<usize>)> = vec![(&dtf.timestamp.first_step, None)];
+    while let Some((step, parent)) = stack.pop() {
+        let index = steps.len();
+        steps.push((step, parent));
+        stack.extend(step.next.iter().rev().map(|child| (child, Some(index))));
+    }
+    let root = tree::assemble(steps, |step, mut hashes| {
+        hashes.sort();
+        step_hash(step, &hashes)
+    });
+
+    let mut output = [0; 32];
+    let mut hasher = Sha256::new();
+    hasher.input(&[VERSION]);
+    hasher.input(&[digest_tag(&dtf.digest_type)]);
+    input_bytes(&mut hasher, &dtf.timestamp.start_digest);
+    // There is always a first step, so always a root
+    hasher.input(&root.unwrap_or([0; 32]));
+    hasher.result(&mut output);
+    output
+}
+
+/// Tag of a digest type in the canonical encoding, which is the same as
+/// the tag of the corresponding op in a serialized timestamp
+fn digest_tag(digest_type: &DigestType) -> u8 {
+    match *digest_type {
+        DigestType::Sha1 => 0x02,
+        DigestType::Ripemd160 => 0x03,
+        DigestType::Sha256 => 0x08
+    }
+}
+
+/// Feed variable-length data to a hasher, preceded by its length so that
+/// adjacent fields cannot run into each other
+fn input_bytes(hasher: &mut Sha256, data: &[u8]) {
+    input_u64(hasher, data.len() as u64);
+    hasher.input(data);
+}
+
+/// Feed a number to a hasher as 8 little-endian bytes
+fn input_u64(hasher: &mut Sha256, n: u64) {
+    let mut bytes = [0; 8];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = (n >> (8 * i)) as u8;
+    }
+    hasher.input(&bytes);
+}
+
+/// Hash of the canonical encoding of a step: a tag byte, any argument,
+/// then the number of children and their hashes. Ops use their tags from
+/// the serialization format, forks use 0xff and attestations use 0x00
+/// followed by their kind. The step's output is left out since it follows
+/// from the ops and the start digest.
+fn step_hash(step: &Step, children: &[[u8; 32]]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    match step.data {
+        StepData::Fork => hasher.input(&[0xff]),
+        StepData::Op(ref op) => {
+            match *op {
+                Op::Sha1 => hasher.input(&[0x02]),
+                Op::Ripemd160 => hasher.input(&[0x03]),
+                Op::Sha256 => hasher.input(&[0x08]),
+                Op::Append(ref data) => {
+                    hasher.input(&[0xf0]);
+                    input_bytes(&mut hasher, data);
+                }
+                Op::Prepend(ref data) => {
+                    hasher.input(&[0xf1]);
+                    input_bytes(&mut hasher, data);
+                }
+                Op::Reverse => hasher.input(&[0xf2]),
+                Op::Hexlify => hasher.input(&[0xf3])
+            }
+        }
+        StepData::Attestation(ref attest) => {
+            hasher.input(&[0x00]);
+            match *attest {
+                Attestation::Bitcoin { height } => {
+                    hasher.input(&[0x00]);
+                    input_u64(&mut hasher, height as u64);
+                }
+                Attestation::Pending { ref uri } => {
+                    hasher.input(&[0x01]);
+                    input_bytes(&mut hasher, uri.as_bytes());
+                }
+                Attestation::Unknown { ref tag, ref data } => {
+                    hasher.input(&[0x02]);
+                    input_bytes(&mut hasher, tag);
+                    input_bytes(&mut hasher, data);
+                }
+            }
+        }
+    }
+    input_u64(&mut hasher, children.len() as u64);
+    for child in children {
+        hasher.input(child);
+    }
+    let mut output = [0; 32];
+    hasher.result(&mut output);
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use ots::DetachedTimestampFile;
+    use ots::attestation::Attestation;
+    use ots::hex::Hexed;
+    use ots::op::Op;
+    use ots::ser::DigestType;
+    use ots::timestamp::Step;
+
+    use testutil::{attest, bitcoin, file, fork, op, pending};
+
+    use super::hash;
+
+    /// A timestamp with a Bitcoin attestation and a pending one
+    fn example(first: Step, second: Step) -> DetachedTimestampFile {
+        file(op(Op::Append(vec![0x01]), fork(vec![
+            op(Op::Sha256, first),
+            op(Op::Prepend(vec![0x02]), op(Op::Sha256, second))
+        ])))
+    }
+
+    #[test]
+    fn stable() {
+        // Changing this means changing the ID of every cached timestamp,
+        // which requires a new `VERSION`
+        let dtf = example(bitcoin(100), pending("https://a.pool.opentimestamps.org"));
+        assert_eq!(format!("{}", Hexed(&hash(&dtf))), "4bd176adc87448e9dfe20b75bd8ca97201e99071b8ff47ca30e86c1585db6ba5");
+    }
+
+    #[test]
+    fn branch_order() {
+        let first = example(bitcoin(100), pending("https://a.pool.opentimestamps.org"));
+        let swapped = file(op(Op::Append(vec![0x01]), fork(vec![
+            op(Op::Prepend(vec![0x02]), op(Op::Sha256, pending("https://a.pool.opentimestamps.org"))),
+            op(Op::Sha256, bitcoin(100))
+        ])));
+        assert_eq!(hash(&first), hash(&swapped));
+    }
+
+    #[test]
+    fn outputs_ignored() {
+        let first = example(bitcoin(100), bitcoin(200));
+        let mut second = example(bitcoin(100), bitcoin(200));
+        second.timestamp.first_step.output = vec![0xff];
+        assert_eq!(hash(&first), hash(&second));
+    }
+
+    #[test]
+    fn ops_and_attestations() {
+        let base = hash(&example(bitcoin(100), bitcoin(200)));
+        let changed = vec![
+            example(bitcoin(101), bitcoin(200)),
+            example(bitcoin(100), pending("https://a.pool.opentimestamps.org")),
+            example(bitcoin(100), attest(Attestation::Unknown { tag: vec![0; 8], data: vec![] })),
+            file(op(Op::Append(vec![0x02]), fork(vec![
+                op(Op::Sha256, bitcoin(100)),
+                op(Op::Prepend(vec![0x02]), op(Op::Sha256, bitcoin(200)))
+            ]))),
+            file(op(Op::Prepend(vec![0x01]), fork(vec![
+                op(Op::Sha256, bitcoin(100)),
+                op(Op::Prepend(vec![0x02]), op(Op::Sha256, bitcoin(200)))
+            ]))),
+            file(op(Op::Append(vec![0x01]), fork(vec![
+                op(Op::Sha1, bitcoin(100)),
+                op(Op::Prepend(vec![0x02]), op(Op::Sha256, bitcoin(200)))
+            ])))
+        ];
+        for dtf in &changed {
+            assert!(hash(dtf) != base);
+        }
+
+        let mut digest_type = example(bitcoin(100), bitcoin(200));
+        digest_type.digest_type = DigestType::Sha1;
+        assert!(hash(&digest_type) != base);
+        let mut start_digest = example(bitcoin(100), bitcoin(200));
+        start_digest.timestamp.start_digest = vec![0xbb; 32];
+        assert!(hash(&start_digest) != base);
+    }
+
+    #[test]
+    fn structure() {
+        // The same steps arranged differently
+        let forked_late = file(op(Op::Sha256, fork(vec![bitcoin(100), bitcoin(200)])));
+        let forked_early = file(fork(vec![op(Op::Sha256, bitcoin(100)), op(Op::Sha256, bitcoin(200))]));
+        assert!(hash(&forked_late) != hash(&forked_early));
+
+        // Fork arity counts, even if the branches are the same
+        let two = file(fork(vec![bitcoin(100), bitcoin(100)]));
+        let three = file(fork(vec![bitcoin(100), bitcoin(100), bitcoin(100)]));
+        assert!(hash(&two) != hash(&three));
+
+        // Length prefixes keep arguments from running together
+        let split = file(op(Op::Append(vec![0x01]), op(Op::Append(vec![0x02, 0x03]), bitcoin(100))));
+        let moved = file(op(Op::Append(vec![0x01, 0x02]), op(Op::Append(vec![0x03]), bitcoin(100))));
+        assert!(hash(&split) != hash(&moved));
+    }
+
+    #[test]
+    fn deep() {
+        // Nothing is recursive, so even absurdly deep trees can be hashed
+        let mut step = bitcoin(100);
+        for _ in 0..100000 {
+            step = op(Op::Sha256, step);
+        }
+        let dtf = file(step);
+        hash(&dtf);
+        // Dropping the tree is recursive, so take it apart by hand
+        let mut step = dtf.timestamp.first_step;
+        while let Some(next) = step.next.pop() {
+            step = next;
+        }
+    }
+}
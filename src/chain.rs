@@ -0,0 +1,640 @@
+// OpenTimestamps Viewer
+// Written in 2017 by
+//   Andrew Poelstra <rust-ots@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Chain
+//!
+//! Sources of Bitcoin block headers, used to check Bitcoin attestations
+//!
+
+use std::{error, fmt, fs, io};
+use std::io::Read;
+use std::path::Path;
+
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+
+/// Length of a serialized Bitcoin block header
+pub const HEADER_LEN: usize = 80;
+
+/// The serialized header of the Bitcoin genesis block
+pub const GENESIS: [u8; HEADER_LEN] = [
+    0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x3b, 0xa3, 0xed, 0xfd,
+    0x7a, 0x7b, 0x12, 0xb2, 0x7a, 0xc7, 0x2c, 0x3e, 0x67, 0x76,
+    0x8f, 0x61, 0x7f, 0xc8, 0x1b, 0xc3, 0x88, 0x8a, 0x51, 0x32,
+    0x3a, 0x9f, 0xb8, 0xaa, 0x4b, 0x1e, 0x5e, 0x4a, 0x29, 0xab,
+    0x5f, 0x49, 0xff, 0xff, 0x00, 0x1d, 0x1d, 0xac, 0x2b, 0x7c
+];
+
+/// The easiest target, in compact form, that a Bitcoin header may have
+pub const POW_LIMIT: u32 = 0x1d00ffff;
+
+/// Errors encountered while obtaining block headers
+#[derive(Debug)]
+pub enum Error {
+    /// I/O error reading a header store
+    Io(io::Error),
+    /// Header store length was not a multiple of 80 bytes
+    BadLength(usize),
+    /// Header at the given height does not commit to its predecessor
+    BrokenChain(usize),
+    /// The first header is not the genesis block
+    BadGenesis,
+    /// Header at the given height does not meet its proof-of-work target,
+    /// or its target is easier than the chain allows
+    BadProofOfWork(usize),
+    /// Failed to talk to a remote header source
+    Rpc(String)
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref e) => write!(f, "I/O error: {}", e),
+            Error::BadLength(len) => write!(f, "header data has length {}, not a multiple of {}", len, HEADER_LEN),
+            Error::BrokenChain(height) => write!(f, "header {} does not commit to header {}", height, height - 1),
+            Error::BadGenesis => f.write_str("header 0 is not the Bitcoin genesis block"),
+            Error::BadProofOfWork(height) => write!(f, "header {} does not have enough proof of work", height),
+            Error::Rpc(ref s) => write!(f, "RPC error: {}", s)
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::Io(_) => "I/O error",
+            Error::BadLength(_) => "bad header data length",
+            Error::BrokenChain(_) => "headers do not form a chain",
+            Error::BadGenesis => "wrong genesis block",
+            Error::BadProofOfWork(_) => "insufficient proof of work",
+            Error::Rpc(_) => "RPC error"
+        }
+    }
+}
+
+/// A serialized Bitcoin block header
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Header(Vec<u8>);
+
+impl Header {
+    /// Wraps a serialized header, checking only its length
+    pub fn from_bytes(data: &[u8]) -> Option<Header> {
+        if data.len() == HEADER_LEN {
+            Some(Header(data.to_vec()))
+        } else {
+            None
+        }
+    }
+
+    /// The hash of the previous block, in internal byte order
+    pub fn prev_blockhash(&self) -> &[u8] {
+        &self.0[4..36]
+    }
+
+    /// The Merkle root committed to by the header, in internal byte order
+    pub fn merkle_root(&self) -> &[u8] {
+        &self.0[36..68]
+    }
+
+    /// The timestamp field of the header
+    pub fn time(&self) -> u32 {
+        (self.0[68] as u32) | (self.0[69] as u32) << 8 |
+        (self.0[70] as u32) << 16 | (self.0[71] as u32) << 24
+    }
+
+    /// The compact encoding of the header's proof-of-work target
+    pub fn bits(&self) -> u32 {
+        (self.0[72] as u32) | (self.0[73] as u32) << 8 |
+        (self.0[74] as u32) << 16 | (self.0[75] as u32) << 24
+    }
+
+    /// The (double-SHA256) hash of the header, in internal byte order
+    pub fn block_hash(&self) -> [u8; 32] {
+        sha256d(&self.0)
+    }
+}
+
+/// The double-SHA256 of some data, as used for Bitcoin block and
+/// transaction hashes, in internal byte order
+pub fn sha256d(data: &[u8]) -> [u8; 32] {
+    let mut output = [0; 32];
+    let mut hasher = Sha256::new();
+    hasher.input(data);
+    hasher.result(&mut output);
+    hasher.reset();
+    hasher.input(&output);
+    hasher.result(&mut output);
+    output
+}
+
+/// Decode a compact proof-of-work target into a big-endian 256-bit number,
+/// or `None` if it is zero, negative or does not fit
+pub fn target(bits: u32) -> Option<[u8; 32]> {
+    if bits & 0x0080_0000 != 0 {
+        return None;
+    }
+    // The three mantissa bytes, most significant first, are multiplied
+    // by 256 to the power of the exponent minus three
+    let exponent = (bits >> 24) as isize;
+    let mantissa = [(bits >> 16) as u8 & 0x7f, (bits >> 8) as u8, bits as u8];
+    let mut target = [0; 32];
+    for (n, byte) in mantissa.iter().enumerate() {
+        let power = exponent - 1 - n as isize;
+        if power < 0 || *byte == 0 {
+            continue;
+        }
+        if power >= 32 {
+            return None;
+        }
+        target[31 - power as usize] = *byte;
+    }
+    if target.iter().all(|byte| *byte == 0) {
+        None
+    } else {
+        Some(target)
+    }
+}
+
+/// Reverse a hash in internal byte order for display
+pub fn reversed(data: &[u8]) -> Vec<u8> {
+    data.iter().rev().map(|x| *x).collect()
+}
+
+/// Something which can produce the block header at a given height
+pub trait HeaderSource: Send + Sync {
+    /// Returns the header at `height`, or `None` if the source does not know it
+    fn header_at(&self, height: usize) -> Result<Option<Header>, Error>;
+
+    /// Returns the median-time-past of the block at `height`, i.e. the median
+    /// timestamp of it and the ten blocks before it, as computed by Bitcoin Core
+    fn median_time_past(&self, height: usize) -> Result<Option<u32>, Error> {
+        let start = if height >= 10 { height - 10 } else { 0 };
+        let mut times = Vec::with_capacity(11);
+        for h in start..height + 1 {
+            match self.header_at(h)? {
+                Some(header) => times.push(header.time()),
+                None => return Ok(None)
+            }
+        }
+        times.sort();
+        Ok(Some(times[times.len() / 2]))
+    }
+
+    /// Returns the number of transactions in the block at `height`, or
+    /// `None` if the source does not know it. Headers alone do not record
+    /// this, so by default it is never known.
+    fn tx_count(&self, _height: usize) -> Result<Option<usize>, Error> {
+        Ok(None)
+    }
+}
+
+/// A header store loaded into memory from a flat file of concatenated
+/// 80-byte headers, starting from the genesis block
+pub struct HeaderStore {
+    data: Vec<u8>
+}
+
+impl HeaderStore {
+    /// Load a header store from disk. If `path` is a directory, every file
+    /// in it is read in lexicographic order and the results concatenated.
+    /// The headers must start from the Bitcoin genesis block, link to one
+    /// another and each meet its proof-of-work target. Difficulty changes
+    /// are not checked, so the store is still trusted to be the best chain.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<HeaderStore, Error> {
+        HeaderStore::load_with(path, &GENESIS, POW_LIMIT)
+    }
+
+    /// Load a header store from disk, checking it against the given genesis
+    /// header and easiest allowed target
+    fn load_with<P: AsRef<Path>>(path: P, genesis: &[u8], pow_limit: u32) -> Result<HeaderStore, Error> {
+        let path = path.as_ref();
+        let mut data = vec![];
+        if path.is_dir() {
+            let mut files = vec![];
+            for entry in fs::read_dir(path)? {
+                let entry_path = entry?.path();
+                if entry_path.is_file() {
+                    files.push(entry_path);
+                }
+            }
+            files.sort();
+            for file in files {
+                fs::File::open(file)?.read_to_end(&mut data)?;
+            }
+        } else {
+            fs::File::open(path)?.read_to_end(&mut data)?;
+        }
+
+        if data.len() % HEADER_LEN != 0 {
+            return Err(Error::BadLength(data.len()));
+        }
+        // Check that the headers actually form a chain, so that a shuffled
+        // or spliced store is refused. A store cut short at a header
+        // boundary still forms a chain, and is simply behind the tip; we
+        // have nothing to check the tip against.
+        let limit = target(pow_limit).unwrap_or([0; 32]);
+        let mut prev_hash = [0; 32];
+        for (height, raw) in data.chunks(HEADER_LEN).enumerate() {
+            let header = Header(raw.to_vec());
+            if height == 0 && raw != genesis {
+                return Err(Error::BadGenesis);
+            }
+            if height > 0 && header.prev_blockhash() != &prev_hash[..] {
+                return Err(Error::BrokenChain(height));
+            }
+            prev_hash = header.block_hash();
+            match target(header.bits()) {
+                Some(target) if target <= limit && reversed(&prev_hash)[..] <= target[..] => {}
+                _ => return Err(Error::BadProofOfWork(height))
+            }
+        }
+
+        Ok(HeaderStore { data: data })
+    }
+
+    /// Number of headers in the store
+    pub fn len(&self) -> usize {
+        self.data.len() / HEADER_LEN
+    }
+}
+
+impl HeaderSource for HeaderStore {
+    fn header_at(&self, height: usize) -> Result<Option<Header>, Error> {
+        if height < self.len() {
+            let start = height * HEADER_LEN;
+            Ok(Header::from_bytes(&self.data[start..start + HEADER_LEN]))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// The header source configured by the operator, if any
+pub struct Chain(pub Option<Box<dyn HeaderSource>>);
+
+impl Chain {
+    /// Accessor for the underlying header source
+    pub fn source(&self) -> Option<&dyn HeaderSource> {
+        self.0.as_ref().map(|s| &**s)
+    }
+}
+
+/// Outcome of checking a Bitcoin attestation against a header source
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Verification {
+    /// The header at the attested height commits to the Merkle root
+    Verified {
+        /// The header in question
+        header: Header,
+        /// Its median-time-past
+        median_time_past: u32
+    },
+    /// The header at the attested height has some other Merkle root
+    Mismatch(Header),
+    /// The header source has no header at the attested height
+    UnknownHeight
+}
+
+/// Checks that the block at `height` has Merkle root `merkle_root`, which
+/// is given in internal byte order (i.e. as the output of the last step
+/// of a timestamp preceding the attestation)
+pub fn verify(source: &dyn HeaderSource, height: usize, merkle_root: &[u8]) -> Result<Verification, Error> {
+    match source.header_at(height)? {
+        Some(header) => {
+            if header.merkle_root() == merkle_root {
+                let median_time_past = source.median_time_past(height)?.unwrap_or(header.time());
+                Ok(Verification::Verified {
+                    header: header,
+                    median_time_past: median_time_past
+                })
+            } else {
+                Ok(Verification::Mismatch(header))
+            }
+        }
+        None => Ok(Verification::UnknownHeight)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+    use std::path::{Path, PathBuf};
+
+    use hex;
+
+    use super::{reversed, sha256d, target, verify, Error, Header, HeaderSource, HeaderStore, Verification, GENESIS, HEADER_LEN};
+
+    /// A header source knowing only the timestamps of its blocks
+    struct Times(Vec<u32>);
+
+    impl HeaderSource for Times {
+        fn header_at(&self, height: usize) -> Result<Option<Header>, Error> {
+            Ok(self.0.get(height).map(|&time| {
+                let mut data = vec![0; HEADER_LEN];
+                data[68] = time as u8;
+                data[69] = (time >> 8) as u8;
+                data[70] = (time >> 16) as u8;
+                data[71] = (time >> 24) as u8;
+                Header(data)
+            }))
+        }
+    }
+
+    #[test]
+    fn median_time_past() {
+        let source = Times((0..20).map(|n| 1000 + 10 * n).collect());
+        assert_eq!(source.header_at(3).unwrap().unwrap().time(), 1030);
+        // Median of the block and the ten before it
+        assert_eq!(source.median_time_past(19).unwrap(), Some(1140));
+        assert_eq!(source.median_time_past(10).unwrap(), Some(1050));
+        // Near genesis there are fewer than eleven blocks to take it over
+        assert_eq!(source.median_time_past(0).unwrap(), Some(1000));
+        assert_eq!(source.median_time_past(1).unwrap(), Some(1010));
+        assert_eq!(source.median_time_past(4).unwrap(), Some(1020));
+        assert_eq!(source.median_time_past(20).unwrap(), None);
+    }
+
+    #[test]
+    fn median_time_past_unordered() {
+        // Block times need not increase, so the median is not simply the
+        // time of the block five back
+        let times = vec![5, 100, 3, 9, 1, 7, 2, 8, 6, 4, 10, 0xffff_ffff];
+        let source = Times(times);
+        assert_eq!(source.median_time_past(10).unwrap(), Some(6));
+        assert_eq!(source.median_time_past(11).unwrap(), Some(7));
+        assert_eq!(source.median_time_past(2).unwrap(), Some(5));
+    }
+
+    /// A target which about half of all hashes meet
+    const EASY: u32 = 0x207fffff;
+
+    /// Mainnet blocks 1 and 2
+    const BLOCK_1: &'static str = "010000006fe28c0ab6f1b372c1a6a246ae63f74f931e8365e15a089c68d6190000000000982051fd1e4ba744bbbe680e1fee14677ba1a3c3540bf7b1cdb606e857233e0e61bc6649ffff001d01e36299";
+    const BLOCK_2: &'static str = "010000004860eb18bf1b1620e37e9490fc8a427514416fd75159ab86688e9a8300000000d5fdcc541e25de1c7a5addedf24858b8bb665c9f36ef744ee42c316022c90f9bb0bc6649ffff001d08d2bd61";
+
+    /// Set the nonce of a header with the `EASY` target so that it meets it
+    fn mine(header: &mut [u8]) {
+        let easy = target(EASY).unwrap();
+        for nonce in 0u32.. {
+            header[76..].copy_from_slice(&[nonce as u8, (nonce >> 8) as u8, (nonce >> 16) as u8, (nonce >> 24) as u8]);
+            if reversed(&sha256d(header))[..] <= easy[..] {
+                return;
+            }
+        }
+    }
+
+    /// A chain of `count` headers, each with Merkle root all bytes its
+    /// height and time ten minutes after the one before
+    fn chain(count: usize) -> Vec<u8> {
+        let mut data = vec![];
+        let mut prev_hash = [0; 32];
+        for height in 0..count {
+            let mut header = vec![0x01, 0x00, 0x00, 0x00];
+            header.extend_from_slice(&prev_hash);
+            header.extend_from_slice(&[height as u8; 32]);
+            let time = 1231006505 + 600 * height as u32;
+            header.extend_from_slice(&[time as u8, (time >> 8) as u8, (time >> 16) as u8, (time >> 24) as u8]);
+            header.extend_from_slice(&[0xff, 0xff, 0x7f, 0x20, 0x00, 0x00, 0x00, 0x00]);
+            mine(&mut header);
+            prev_hash = sha256d(&header);
+            data.extend(header);
+        }
+        data
+    }
+
+    /// Load a header store starting from the genesis block of `chain`
+    fn load<P: AsRef<Path>>(path: P) -> Result<HeaderStore, Error> {
+        HeaderStore::load_with(path, &chain(1), EASY)
+    }
+
+    /// The first three mainnet headers
+    fn mainnet() -> Vec<u8> {
+        let mut data = GENESIS.to_vec();
+        data.extend(hex::decode(BLOCK_1).unwrap());
+        data.extend(hex::decode(BLOCK_2).unwrap());
+        data
+    }
+
+    /// A fresh, empty directory for this test
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("ots-viewer-chain-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn load_file() {
+        let dir = temp_dir("file");
+        let data = chain(20);
+        fs::write(dir.join("headers"), &data).unwrap();
+        let store = load(dir.join("headers")).unwrap();
+        assert_eq!(store.len(), 20);
+        for height in 0..20 {
+            let header = store.header_at(height).unwrap().unwrap();
+            assert_eq!(&header.0[..], &data[height * HEADER_LEN..(height + 1) * HEADER_LEN]);
+            assert_eq!(header.merkle_root(), &[height as u8; 32][..]);
+        }
+        assert_eq!(store.header_at(20).unwrap(), None);
+
+        fs::write(dir.join("empty"), []).unwrap();
+        assert_eq!(load(dir.join("empty")).unwrap().len(), 0);
+        assert!(load(dir.join("missing")).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn load_directory() {
+        let dir = temp_dir("directory");
+        let data = chain(30);
+        // Files are read in lexicographic order, whatever order they were written
+        fs::write(dir.join("b"), &data[10 * HEADER_LEN..25 * HEADER_LEN]).unwrap();
+        fs::write(dir.join("a"), &data[..10 * HEADER_LEN]).unwrap();
+        fs::write(dir.join("c"), &data[25 * HEADER_LEN..]).unwrap();
+        fs::create_dir(dir.join("subdirectory")).unwrap();
+        let store = load(&dir).unwrap();
+        assert_eq!(store.len(), 30);
+        assert_eq!(store.header_at(29).unwrap().unwrap().merkle_root(), &[29; 32][..]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn load_bad_length() {
+        let dir = temp_dir("length");
+        let mut data = chain(3);
+        data.pop();
+        fs::write(dir.join("headers"), &data).unwrap();
+        match load(dir.join("headers")) {
+            Err(Error::BadLength(len)) => assert_eq!(len, 3 * HEADER_LEN - 1),
+            other => panic!("expected a bad length, got {:?}", other.map(|store| store.len()))
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn load_broken_chain() {
+        let dir = temp_dir("broken");
+        let data = chain(10);
+
+        // Two headers swapped
+        let mut swapped = data.clone();
+        for n in 0..HEADER_LEN {
+            swapped.swap(4 * HEADER_LEN + n, 5 * HEADER_LEN + n);
+        }
+        fs::write(dir.join("swapped"), &swapped).unwrap();
+        match load(dir.join("swapped")) {
+            Err(Error::BrokenChain(height)) => assert_eq!(height, 4),
+            other => panic!("expected a broken chain, got {:?}", other.map(|store| store.len()))
+        }
+
+        // A header modified, breaking the link from the one after it
+        let mut modified = data.clone();
+        modified[7 * HEADER_LEN + 40] ^= 1;
+        mine(&mut modified[7 * HEADER_LEN..8 * HEADER_LEN]);
+        fs::write(dir.join("modified"), &modified).unwrap();
+        match load(dir.join("modified")) {
+            Err(Error::BrokenChain(height)) => assert_eq!(height, 8),
+            other => panic!("expected a broken chain, got {:?}", other.map(|store| store.len()))
+        }
+
+        // A header missing from the middle
+        let mut spliced = data[..3 * HEADER_LEN].to_vec();
+        spliced.extend_from_slice(&data[4 * HEADER_LEN..]);
+        fs::write(dir.join("spliced"), &spliced).unwrap();
+        match load(dir.join("spliced")) {
+            Err(Error::BrokenChain(height)) => assert_eq!(height, 3),
+            other => panic!("expected a broken chain, got {:?}", other.map(|store| store.len()))
+        }
+
+        // Truncation at a header boundary cannot be detected
+        fs::write(dir.join("truncated"), &data[..6 * HEADER_LEN]).unwrap();
+        assert_eq!(load(dir.join("truncated")).unwrap().len(), 6);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn merkle_roots() {
+        let dir = temp_dir("verify");
+        fs::write(dir.join("headers"), chain(20)).unwrap();
+        let store = load(dir.join("headers")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        match verify(&store, 15, &[15; 32]).unwrap() {
+            Verification::Verified { header, median_time_past } => {
+                assert_eq!(header, store.header_at(15).unwrap().unwrap());
+                assert_eq!(median_time_past, 1231006505 + 600 * 10);
+            }
+            other => panic!("expected verification, got {:?}", other)
+        }
+        assert_eq!(
+            verify(&store, 15, &[14; 32]).unwrap(),
+            Verification::Mismatch(store.header_at(15).unwrap().unwrap())
+        );
+        assert_eq!(verify(&store, 20, &[20; 32]).unwrap(), Verification::UnknownHeight);
+    }
+
+    #[test]
+    fn targets() {
+        let mut limit = [0; 32];
+        limit[4] = 0xff;
+        limit[5] = 0xff;
+        assert_eq!(target(0x1d00ffff), Some(limit));
+        let mut small = [0; 32];
+        small[31] = 0x12;
+        assert_eq!(target(0x01123456), Some(small));
+        small[30] = 0x12;
+        small[31] = 0x34;
+        assert_eq!(target(0x02123456), Some(small));
+        let mut large = [0; 32];
+        large[0] = 0x7f;
+        large[1] = 0xff;
+        assert_eq!(target(0x207fff00), Some(large));
+
+        // Zero, negative and overflowing targets
+        assert_eq!(target(0x1d000000), None);
+        assert_eq!(target(0x01003456), None);
+        assert_eq!(target(0x1d80ffff), None);
+        assert_eq!(target(0x217fffff), None);
+        assert_eq!(target(0x2300ffff), None);
+    }
+
+    #[test]
+    fn load_mainnet() {
+        let dir = temp_dir("mainnet");
+        fs::write(dir.join("headers"), mainnet()).unwrap();
+        let store = HeaderStore::load(dir.join("headers")).unwrap();
+        assert_eq!(store.len(), 3);
+        let hash = store.header_at(2).unwrap().unwrap().block_hash();
+        assert_eq!(hex::encode(reversed(&hash)), "000000006a625f06636b8bb6ac7b960a8d03705d1ace08b1a19da3fdcc99ddbd");
+
+        fs::write(dir.join("empty"), []).unwrap();
+        assert_eq!(HeaderStore::load(dir.join("empty")).unwrap().len(), 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn load_bad_genesis() {
+        let dir = temp_dir("genesis");
+        // A chain that is otherwise fine, but not mainnet
+        fs::write(dir.join("other"), chain(3)).unwrap();
+        match HeaderStore::load(dir.join("other")) {
+            Err(Error::BadGenesis) => {}
+            other => panic!("expected a bad genesis, got {:?}", other.map(|store| store.len()))
+        }
+
+        // Mainnet with its genesis block altered
+        let mut altered = mainnet();
+        altered[40] ^= 1;
+        fs::write(dir.join("altered"), &altered).unwrap();
+        match HeaderStore::load(dir.join("altered")) {
+            Err(Error::BadGenesis) => {}
+            other => panic!("expected a bad genesis, got {:?}", other.map(|store| store.len()))
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn load_bad_proof_of_work() {
+        let dir = temp_dir("work");
+
+        // A header whose hash misses its target
+        let mut data = mainnet();
+        data[2 * HEADER_LEN + 76] ^= 1;
+        fs::write(dir.join("nonce"), &data).unwrap();
+        match HeaderStore::load(dir.join("nonce")) {
+            Err(Error::BadProofOfWork(height)) => assert_eq!(height, 2),
+            other => panic!("expected bad proof of work, got {:?}", other.map(|store| store.len()))
+        }
+
+        // A header meeting a target easier than the chain allows
+        let mut data = chain(3);
+        data[2 * HEADER_LEN + 75] = 0x21;
+        mine(&mut data[2 * HEADER_LEN..]);
+        fs::write(dir.join("easy"), &data).unwrap();
+        match HeaderStore::load_with(dir.join("easy"), &data[..HEADER_LEN], EASY) {
+            Err(Error::BadProofOfWork(height)) => assert_eq!(height, 2),
+            other => panic!("expected bad proof of work, got {:?}", other.map(|store| store.len()))
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,118 @@
+// OpenTimestamps Viewer
+// Written in 2017 by
+//   Andrew Poelstra <rust-ots@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Document
+//!
+//! Streaming hashing of original documents, to check them against timestamps
+//!
+
+use std::io::{self, Read};
+
+use crypto::digest::Digest;
+use crypto::ripemd160::Ripemd160;
+use crypto::sha1::Sha1;
+use crypto::sha2::Sha256;
+use ots::ser::DigestType;
+
+/// Size of the buffer used to stream documents through the hashers
+const CHUNK_SIZE: usize = 65536;
+
+/// Digests of a document under every hash function a timestamp may use.
+/// Documents may arrive before we know which one is needed, and may be
+/// too large to keep around, so all are computed in a single pass.
+pub struct DocumentDigests {
+    sha1: Vec<u8>,
+    sha256: Vec<u8>,
+    ripemd160: Vec<u8>
+}
+
+fn finish<D: Digest>(hasher: &mut D) -> Vec<u8> {
+    let mut output = vec![0; hasher.output_bytes()];
+    hasher.result(&mut output);
+    output
+}
+
+impl DocumentDigests {
+    /// Hash everything readable from `reader`, without buffering it
+    pub fn hash_reader<R: Read>(mut reader: R) -> io::Result<DocumentDigests> {
+        let mut sha1 = Sha1::new();
+        let mut sha256 = Sha256::new();
+        let mut ripemd160 = Ripemd160::new();
+        let mut buf = vec![0; CHUNK_SIZE];
+        loop {
+            let n = match reader.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e)
+            };
+            sha1.input(&buf[..n]);
+            sha256.input(&buf[..n]);
+            ripemd160.input(&buf[..n]);
+        }
+        Ok(DocumentDigests {
+            sha1: finish(&mut sha1),
+            sha256: finish(&mut sha256),
+            ripemd160: finish(&mut ripemd160)
+        })
+    }
+
+    /// The digest of the document under the given hash function
+    pub fn get(&self, digest_type: &DigestType) -> &[u8] {
+        match *digest_type {
+            DigestType::Sha1 => &self.sha1,
+            DigestType::Sha256 => &self.sha256,
+            DigestType::Ripemd160 => &self.ripemd160
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Read};
+
+    use ots::hex::Hexed;
+    use ots::ser::DigestType;
+
+    use super::DocumentDigests;
+
+    fn hexes(digests: &DocumentDigests) -> (String, String, String) {
+        (
+            format!("{}", Hexed(digests.get(&DigestType::Sha1))),
+            format!("{}", Hexed(digests.get(&DigestType::Sha256))),
+            format!("{}", Hexed(digests.get(&DigestType::Ripemd160)))
+        )
+    }
+
+    #[test]
+    fn empty() {
+        let digests = DocumentDigests::hash_reader(io::empty()).unwrap();
+        assert_eq!(hexes(&digests), (
+            "da39a3ee5e6b4b0d3255bfef95601890afd80709".to_owned(),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855".to_owned(),
+            "9c1185a5c5e9fc54612808977ee8f548b2258d31".to_owned()
+        ));
+    }
+
+    #[test]
+    fn many_chunks() {
+        // A million "a"s, over sixteen reads of `CHUNK_SIZE`
+        let digests = DocumentDigests::hash_reader(io::repeat(b'a').take(1_000_000)).unwrap();
+        assert_eq!(hexes(&digests), (
+            "34aa973cd4c4daa4f61eeb2bdbad27316534016f".to_owned(),
+            "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0".to_owned(),
+            "52783243c1697bdbe16d37f97f68f08325dc1528".to_owned()
+        ));
+    }
+}
@@ -0,0 +1,349 @@
+// OpenTimestamps Viewer
+// Written in 2017 by
+//   Andrew Poelstra <rust-ots@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Graph
+//!
+//! Drawing of timestamp trees as SVG images, with the start digest at the
+//! top and attestations as coloured leaves at the bottom
+//!
+
+use std::fmt::Write;
+
+use ots::attestation::Attestation;
+use ots::hex::Hexed;
+use ots::op::Op;
+use ots::timestamp::{Step, StepData};
+
+use chain::{self, HeaderSource, Verification};
+use render::{format_height, short_hex};
+use tree::{self, Limits};
+
+/// Horizontal space given to each leaf of the tree
+const COL_WIDTH: usize = 200;
+/// Vertical space given to each level of the tree
+const ROW_HEIGHT: usize = 50;
+/// Size of the box drawn for each step
+const NODE_WIDTH: usize = 180;
+const NODE_HEIGHT: usize = 26;
+/// Space around the whole drawing
+const MARGIN: usize = 10;
+
+/// A step of the tree, with its position once laid out
+struct Node {
+    /// Short text drawn in the box
+    label: String,
+    /// Full description, shown on hover
+    title: String,
+    fill: &'static str,
+    depth: usize,
+    parent: Option<usize>,
+    has_children: bool,
+    /// Leftmost and rightmost leaf columns below this node
+    first_leaf: usize,
+    last_leaf: usize
+}
+
+impl Node {
+    fn new(label: String, title: String, fill: &'static str, depth: usize, parent: Option<usize>) -> Node {
+        Node {
+            label: label,
+            title: title,
+            fill: fill,
+            depth: depth,
+            parent: parent,
+            has_children: false,
+            first_leaf: 0,
+            last_leaf: 0
+        }
+    }
+
+    /// Horizontal centre of the node
+    fn x(&self) -> usize {
+        MARGIN + (self.first_leaf + self.last_leaf) * COL_WIDTH / 2 + COL_WIDTH / 2
+    }
+
+    /// Vertical centre of the node
+    fn y(&self) -> usize {
+        MARGIN + self.depth * ROW_HEIGHT + NODE_HEIGHT / 2
+    }
+}
+
+/// Escape text for inclusion in XML
+fn escape(s: &str) -> String {
+    let mut ret = String::with_capacity(s.len());
+    for ch in s.chars() {
+        match ch {
+            '&' => ret.push_str("&amp;"),
+            '<' => ret.push_str("&lt;"),
+            '>' => ret.push_str("&gt;"),
+            '"' => ret.push_str("&quot;"),
+            '\'' => ret.push_str("&apos;"),
+            ch => ret.push(ch)
+        }
+    }
+    ret
+}
+
+/// Shorten a label to fit in a node
+fn truncate(s: String) -> String {
+    if s.chars().count() > 24 {
+        let mut ret: String = s.chars().take(21).collect();
+        ret.push_str("...");
+        ret
+    } else {
+        s
+    }
+}
+
+/// Describe a single step as a node of the graph
+fn describe(step: &Step, input: &[u8], chain: Option<&dyn HeaderSource>) -> (String, String, &'static str) {
+    match step.data {
+        StepData::Fork => (
+            format!("Fork ({})", step.next.len()),
+            format!("Fork into {} paths", step.next.len()),
+            "#DEF"
+        ),
+        StepData::Op(ref op) => {
+            let label = match *op {
+                Op::Append(ref data) => format!("Append({})", short_hex(data)),
+                Op::Prepend(ref data) => format!("Prepend({})", short_hex(data)),
+                _ => format!("{}", op)
+            };
+            (label, format!("{}", op), "#FFF")
+        }
+        StepData::Attestation(Attestation::Bitcoin { height }) => {
+            let fill = match chain.map(|source| chain::verify(source, height, input)) {
+                Some(Ok(Verification::Verified { .. })) => "#CFC",
+                Some(Ok(Verification::Mismatch(_))) => "#FCC",
+                _ => "#FFD"
+            };
+            (
+                format!("Bitcoin block {}", format_height(height)),
+                format!("Bitcoin block {} with Merkle root {}", height, Hexed(&chain::reversed(input))),
+                fill
+            )
+        }
+        StepData::Attestation(Attestation::Pending { ref uri }) => (
+            format!("Pending: {}", uri),
+            format!("Pending attestation from {}", uri),
+            "#EEE"
+        ),
+        StepData::Attestation(Attestation::Unknown { ref tag, .. }) => (
+            "Unknown attestation".to_owned(),
+            format!("Unknown attestation {}", Hexed(tag)),
+            "#EEE"
+        )
+    }
+}
+
+/// Draw a timestamp as an SVG image, failing if the timestamp is malformed
+/// or exceeds `limits`
+pub fn render_graph(first_step: &Step, start_digest: &[u8], chain: Option<&dyn HeaderSource>, limits: &Limits) -> Result<String, tree::Error> {
+    let mut nodes = vec![Node::new(
+        format!("Digest {}", short_hex(start_digest)),
+        format!("Document digest {}", Hexed(start_digest)),
+        "#FDF",
+        0,
+        None
+    )];
+
+    // Steps are visited depth-first, so the parent of each step is the
+    // most recent one visited at the level above it
+    let mut path = vec![0];
+    tree::walk(first_step, start_digest, limits, |visit| {
+        let depth = visit.depth + 1;
+        path.truncate(depth);
+        let parent = path[depth - 1];
+        nodes[parent].has_children = true;
+        let (label, title, fill) = describe(visit.step, visit.input, chain);
+        path.push(nodes.len());
+        nodes.push(Node::new(truncate(label), title, fill, depth, Some(parent)));
+    })?;
+
+    // Give each leaf its own column, left to right, then centre every other
+    // node over the leaves below it, i.e. from the first leaf of its first
+    // child to the last leaf of its last child
+    let mut columns = 0;
+    for node in &mut nodes {
+        if !node.has_children {
+            node.first_leaf = columns;
+            node.last_leaf = columns;
+            columns += 1;
+        }
+    }
+    let links: Vec<(usize, Option<usize>)> = nodes.iter().enumerate().map(|(n, node)| (n, node.parent)).collect();
+    tree::assemble(links, |n, children: Vec<(usize, usize)>| {
+        if let (Some(first), Some(last)) = (children.first(), children.last()) {
+            nodes[n].first_leaf = first.0;
+            nodes[n].last_leaf = last.1;
+        }
+        (nodes[n].first_leaf, nodes[n].last_leaf)
+    });
+
+    let depth = nodes.iter().map(|node| node.depth).max().unwrap_or(0);
+    let width = 2 * MARGIN + columns * COL_WIDTH;
+    let height = 2 * MARGIN + depth * ROW_HEIGHT + NODE_HEIGHT;
+
+    let mut svg = String::new();
+    // Writing to a String cannot fail
+    let _ = write!(svg, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let _ = write!(svg, "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{0}\" height=\"{1}\" viewBox=\"0 0 {0} {1}\" font-family=\"monospace\" font-size=\"12\">\n", width, height);
+    svg.push_str("<defs><marker id=\"arrow\" viewBox=\"0 0 10 10\" refX=\"10\" refY=\"5\" markerWidth=\"6\" markerHeight=\"6\" orient=\"auto\"><path d=\"M 0 0 L 10 5 L 0 10 z\" /></marker></defs>\n");
+    for node in &nodes {
+        if let Some(parent) = node.parent {
+            let parent = &nodes[parent];
+            let _ = write!(
+                svg,
+                "<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"black\" marker-end=\"url(#arrow)\" />\n",
+                parent.x(), parent.y() + NODE_HEIGHT / 2, node.x(), node.y() - NODE_HEIGHT / 2
+            );
+        }
+    }
+    for node in &nodes {
+        let _ = write!(
+            svg,
+            "<g><title>{}</title><rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" rx=\"4\" fill=\"{}\" stroke=\"black\" /><text x=\"{}\" y=\"{}\" text-anchor=\"middle\">{}</text></g>\n",
+            escape(&node.title),
+            node.x() - NODE_WIDTH / 2, node.y() - NODE_HEIGHT / 2, NODE_WIDTH, NODE_HEIGHT, node.fill,
+            node.x(), node.y() + 4, escape(&node.label)
+        );
+    }
+    svg.push_str("</svg>\n");
+    Ok(svg)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use ots::attestation::Attestation;
+    use ots::op::Op;
+    use ots::timestamp::Step;
+
+    use chain::{Error, Header, HeaderSource, HEADER_LEN};
+    use testutil::{attest, bitcoin, file, fork, op, pending, DIGEST};
+    use tree::Limits;
+
+    use super::render_graph;
+
+    /// A header source knowing blocks below height 10, whose Merkle roots
+    /// are all 0xee bytes except that of block 1
+    struct Roots(Vec<u8>);
+
+    impl HeaderSource for Roots {
+        fn header_at(&self, height: usize) -> Result<Option<Header>, Error> {
+            if height >= 10 {
+                return Ok(None);
+            }
+            let mut data = vec![0xee; HEADER_LEN];
+            if height == 1 {
+                data[36..68].copy_from_slice(&self.0);
+            }
+            Ok(Header::from_bytes(&data))
+        }
+    }
+
+    /// A box drawn for a step: its hover text, position, fill and label
+    #[derive(PartialEq, Eq, Debug)]
+    struct Drawn {
+        title: String,
+        x: usize,
+        y: usize,
+        fill: String,
+        label: String
+    }
+
+    /// The text of `line` between `start` and the next `end` after it
+    fn between<'a>(line: &'a str, start: &str, end: &str) -> &'a str {
+        let from = line.find(start).unwrap() + start.len();
+        let to = from + line[from..].find(end).unwrap();
+        &line[from..to]
+    }
+
+    /// Every box in the drawing, in the order the steps were visited
+    fn boxes(svg: &str) -> Vec<Drawn> {
+        svg.lines().filter(|line| line.starts_with("<g>")).map(|line| {
+            let rect = between(line, "<rect ", "/>");
+            Drawn {
+                title: between(line, "<title>", "</title>").to_owned(),
+                x: between(rect, "x=\"", "\"").parse().unwrap(),
+                y: between(rect, "y=\"", "\"").parse().unwrap(),
+                fill: between(rect, "fill=\"", "\"").to_owned(),
+                label: between(line, "text-anchor=\"middle\">", "</text>").to_owned()
+            }
+        }).collect()
+    }
+
+    /// A timestamp with one of each kind of attestation, with Bitcoin
+    /// attestations that verify, fail to and are unknown to `Roots`
+    fn timestamp() -> Step {
+        fork(vec![
+            op(Op::Sha256, bitcoin(1)),
+            pending("https://x/<&>\"'"),
+            fork(vec![
+                bitcoin(2),
+                bitcoin(20),
+                attest(Attestation::Unknown { tag: vec![0; 8], data: vec![] })
+            ])
+        ])
+    }
+
+    #[test]
+    fn layout() {
+        let dtf = file(timestamp());
+        let svg = render_graph(&dtf.timestamp.first_step, &DIGEST, None, &Limits::default()).unwrap();
+        // Five leaves, and four levels below the digest
+        assert!(svg.contains("width=\"1020\" height=\"196\""));
+        // Top left corners of boxes 180 wide, in columns 200 wide and rows
+        // 50 high, each centred over the leaves below it
+        let positions: Vec<(usize, usize)> = boxes(&svg).iter().map(|b| (b.x, b.y)).collect();
+        assert_eq!(positions, vec![
+            (420, 10),  // Digest, over all five leaves
+            (420, 60),  // Fork, likewise
+            (20, 110),  // SHA256, over the first leaf
+            (20, 160),  // Bitcoin block 1, the first leaf
+            (220, 110), // Pending, the second leaf
+            (620, 110), // Fork, over the last three leaves
+            (420, 160),
+            (620, 160),
+            (820, 160)
+        ]);
+        // Every step but the digest has one arrow into it
+        assert_eq!(svg.matches("<line ").count(), 8);
+    }
+
+    #[test]
+    fn fill_colours() {
+        let dtf = file(timestamp());
+        let verified_root = dtf.timestamp.first_step.next[0].output.clone();
+        let svg = render_graph(&dtf.timestamp.first_step, &DIGEST, Some(&Roots(verified_root)), &Limits::default()).unwrap();
+        let fills: Vec<String> = boxes(&svg).into_iter().map(|b| b.fill).collect();
+        assert_eq!(fills, vec!["#FDF", "#DEF", "#FFF", "#CFC", "#EEE", "#DEF", "#FCC", "#FFD", "#EEE"]);
+
+        // Without a header source, nothing is verified either way
+        let svg = render_graph(&dtf.timestamp.first_step, &DIGEST, None, &Limits::default()).unwrap();
+        let boxes = boxes(&svg);
+        assert_eq!(boxes[3].fill, "#FFD");
+        assert_eq!(boxes[6].fill, "#FFD");
+    }
+
+    #[test]
+    fn escaping() {
+        let dtf = file(timestamp());
+        let svg = render_graph(&dtf.timestamp.first_step, &DIGEST, None, &Limits::default()).unwrap();
+        assert!(!svg.contains("<&>"));
+        let pending = &boxes(&svg)[4];
+        assert_eq!(pending.title, "Pending attestation from https://x/&lt;&amp;&gt;&quot;&apos;");
+        assert_eq!(pending.label, "Pending: https://x/&lt;&amp;&gt;&quot;&apos;");
+    }
+}
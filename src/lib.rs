@@ -0,0 +1,42 @@
+// OpenTimestamps Viewer
+// Written in 2017 by
+//   Andrew Poelstra <rust-ots@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # OpenTimestamps Viewer
+//!
+//! Rendering of .ots files, shared by the HTTP server and the fuzz tests
+//!
+
+// Coding conventions
+#![deny(non_upper_case_globals)]
+#![deny(non_camel_case_types)]
+#![deny(non_snake_case)]
+#![deny(unused_mut)]
+
+extern crate chrono;
+extern crate crypto;
+extern crate opentimestamps as ots;
+#[cfg(test)] extern crate hex;
+#[macro_use] extern crate serde;
+
+pub mod canonical;
+pub mod chain;
+pub mod graph;
+pub mod merge;
+pub mod prune;
+pub mod render;
+pub mod tree;
+pub mod tx;
+
+#[cfg(test)] mod testutil;
+
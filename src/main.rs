@@ -16,6 +16,38 @@
 //!
 //! HTTP server which provides a pretty view of .ots files
 //!
+//! Bitcoin attestations are checked against a store of block headers if
+//! one is configured, by setting `header_store` in `Rocket.toml` to a
+//! file (or directory of files) of concatenated 80-byte headers. Instead,
+//! `bitcoind_url` may be set to query a Bitcoin Core node over JSON-RPC,
+//! authenticating with `bitcoind_cookie` or `bitcoind_user`/`bitcoind_pass`
+//! and giving up after `bitcoind_timeout` seconds (default 10). Only
+//! bitcoind knows how many transactions each block has, which is used to
+//! sanity-check the depth of Merkle paths into blocks.
+//!
+//! Timestamps nested more than `max_tree_depth` steps deep (default 1024)
+//! or with more than `max_tree_steps` steps (default 65536) are refused.
+//!
+//! Pending attestations may be upgraded by contacting the calendar servers
+//! listed in `calendar_whitelist` (a comma-separated list of URIs, where
+//! `https://*.example.com` matches any subdomain), each of which is given
+//! `calendar_timeout` seconds (default 10) to respond. Cached timestamps
+//! with only pending attestations are upgraded in the background every
+//! `upgrade_interval` seconds (default 600; 0 disables this), backing off
+//! on failure. Superseded IDs are linked to their upgraded versions.
+//!
+//! The original document may be uploaded alongside a timestamp, or later
+//! from its view page, to check it against the timestamp. Documents are
+//! hashed as they stream in and are never stored.
+//!
+//! Every cached timestamp is indexed by its document digest, so that all
+//! stored timestamps of a document can be listed at `/digest/<hex>`. The
+//! index is built on launch for caches written before it existed.
+//!
+//! Document IDs hash a canonical, versioned encoding of the whole
+//! timestamp. Timestamps cached under an older version of the encoding
+//! are moved on launch, and their old IDs linked to the new ones.
+//!
 
 // Coding conventions
 #![deny(non_upper_case_globals)]
@@ -25,166 +57,195 @@
 
 #![feature(decl_macro)]
 
-extern crate bitcoin;
 extern crate crypto;
-extern crate rocket_multipart_form_data;
+extern crate hex;
+extern crate reqwest;
+extern crate serde_json;
+extern crate multipart;
 extern crate opentimestamps as ots;
 extern crate rocket_contrib;
 #[macro_use] extern crate rocket;
 #[macro_use] extern crate serde;
+extern crate ots_viewer;
+
+mod api;
+mod bitcoind;
+mod cache;
+mod calendar;
+mod document;
+mod multipart_stream;
+mod scheduler;
+mod upload;
+#[cfg(test)]
+#[path = "testutil.rs"]
+mod testutil;
 
 use std::collections::HashMap;
-use std::fs;
+use std::io;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use bitcoin::blockdata::transaction::Transaction;
-use bitcoin::network::serialize::{deserialize, BitcoinHash};
-use crypto::digest::Digest;
-use crypto::sha2::Sha256;
-use rocket_multipart_form_data::{MultipartFormDataOptions, MultipartFormData, MultipartFormDataField};
-use ots::attestation::Attestation;
-use ots::timestamp::{Step, StepData};
-use ots::op::Op;
+use cache::{Cache, DocId};
+use calendar::Calendars;
+use multipart_stream::MultipartStream;
+use ots::DetachedTimestampFile;
 use ots::hex::Hexed;
-use rocket::Data;
-use rocket::http::ContentType;
-use rocket::response::content;
+use ots::ser::DigestType;
+use ots_viewer::{canonical, chain, graph, merge, prune, render, tree};
+use ots_viewer::chain::Chain;
+use ots_viewer::render::{DisplayedBranch, DisplayedDocumentCheck, DisplayedPath, DisplayedVerdict};
+use ots_viewer::tree::Limits;
+use rocket::{Config, State};
+use rocket::fairing::AdHoc;
+use rocket::request::Form;
+use rocket::http::{ContentType, Status};
+use rocket::response::{content, status};
 use rocket::response::{Redirect, NamedFile};
 use rocket_contrib::templates::Template;
 
 #[derive(Debug, Serialize)]
-struct DisplayedStep {
-    prefix: String,
-    result: String,
-    reason: String,
-    class: &'static str
+struct DisplayedTimestamp {
+    id: DocId,
+    title: String,
+    short_hash: String,
+    start_hash: String,
+    digest_type: String,
+    has_pending: bool,
+    has_bitcoin: bool,
+    verdict: DisplayedVerdict,
+    document: Option<DisplayedDocumentCheck>,
+    tree: DisplayedBranch
 }
 
 #[derive(Debug, Serialize)]
-struct DisplayedTimestamp {
-    id: String,
+struct DisplayedPaths {
+    id: DocId,
     title: String,
+    short_hash: String,
     start_hash: String,
     digest_type: String,
-    steps: Vec<DisplayedStep>
+    selected: Option<usize>,
+    paths: Vec<DisplayedPath>
 }
 
-fn render_steps(step: &Step, vec: &mut Vec<DisplayedStep>, prev_data: &[u8], prefix: String) {
-    match step.data {
-        StepData::Fork => {
-            vec.push(DisplayedStep {
-                prefix: prefix.clone(),
-                result: format!("Fork into <b>{}</b> paths", step.next.len()),
-                reason: "Fork".to_owned(),
-                class: "step_fork"
-            });
-            for (n, next) in step.next.iter().enumerate() {
-                let new_prefix = if prefix.is_empty() {
-                    format!("{} ", n + 1)
-                } else {
-                    format!("{}- {} ", prefix, n + 1)
-                };
-                render_steps(next, vec, prev_data, new_prefix);
-            }
-        }
-        StepData::Op(ref op) => {
-            match *op {
-                Op::Sha1 | Op::Sha256 | Op::Ripemd160 |
-                Op::Reverse | Op::Hexlify => {
-                    vec.push(DisplayedStep {
-                        prefix: prefix.clone(),
-                        result: format!("<tt>{}</tt>", Hexed(&step.output)),
-                        reason: format!("{}", op),
-                        class: "step_op"
-                    });
-                }
-                Op::Append(ref newdata) => {
-                    vec.push(DisplayedStep {
-                        prefix: prefix.clone(),
-                        result: format!("<tt>{}<font color=\"green\">{}</font></tt>", Hexed(prev_data), Hexed(newdata)),
-                        reason: format!("Append({}...)", Hexed(&newdata[0..3])),
-                        class: "step_op"
-                    });
-                    // Notice valid bitcoin transactions
-                    if let Ok(tx) = deserialize::<Transaction>(&step.output) {
-                        vec.push(DisplayedStep {
-                            prefix: prefix.clone(),
-                            result: format!("Bitcoin transaction <b>{}</b>", tx.bitcoin_hash()),
-                            reason: "(Parse TX)".to_owned(),
-                            class: "step_parse"
-                        });
-                    }
-                }
-                Op::Prepend(ref newdata) => {
-                    vec.push(DisplayedStep {
-                        prefix: prefix.clone(),
-                        result: format!("<tt><font color=\"green\">{}</font>{}</tt>", Hexed(newdata), Hexed(prev_data)),
-                        reason: format!("Prepend({}...)", Hexed(&newdata[0..3])),
-                        class: "step_op"
-                    });
-                }
+#[derive(Debug, Serialize)]
+struct DisplayedComparison {
+    title: String,
+    /// Descriptions of the two timestamps being compared
+    first: String,
+    second: String,
+    comparison: Option<merge::Comparison>
+}
+
+#[derive(Debug, Serialize)]
+struct DisplayedSearch {
+    title: String,
+    /// What was searched for
+    query: String,
+    results: Vec<SearchResult>
+}
+
+#[derive(Debug, Serialize)]
+struct SearchResult {
+    id: DocId,
+    digest_type: String,
+    has_bitcoin: bool,
+    has_pending: bool
+}
+
+#[derive(FromForm)]
+struct PruneOptions {
+    /// Either "shortest" or "earliest"
+    by: String
+}
+
+/// Render the error page
+fn error_page(title: &str, error: String) -> Template {
+    let mut context = HashMap::new();
+    context.insert("title", title.to_owned());
+    context.insert("error", error);
+    Template::render("error", &context)
+}
+
+// File viewer
+#[get("/view/<id>?<digest>")]
+fn view(id: DocId, digest: Option<String>, chain: State<Chain>, limits: State<Limits>) -> Template {
+    match cache::load(&cache::resolve(&id)) {
+        Ok(dtf) => {
+            let start_digest = &dtf.timestamp.start_digest;
+            let document = match digest {
+                Some(digest) => match hex::decode(&digest) {
+                    Ok(digest) => Some(render::render_document_check(&digest, start_digest)),
+                    Err(_) => return error_page("View Timestamp", "The document digest must be given in hex".to_owned())
+                },
+                None => None
+            };
+            let tree = match render::render_tree(&dtf.timestamp.first_step, start_digest, chain.source(), &limits) {
+                Ok(tree) => tree,
+                Err(e) => return error_page("View Timestamp", format!("Cannot display timestamp: {}", e))
             };
-            render_steps(&step.next[0], vec, &step.output, prefix);
+            let display = DisplayedTimestamp {
+                id: cache::doc_id(&dtf),
+                title: "Timestamp".to_owned(),
+                short_hash: format!("{}", Hexed(&start_digest[..start_digest.len().min(6)])),
+                start_hash: format!("{}", Hexed(start_digest)),
+                digest_type: format!("{}", dtf.digest_type),
+                has_pending: calendar::has_pending(&dtf.timestamp.first_step),
+                has_bitcoin: calendar::has_bitcoin(&dtf.timestamp.first_step),
+                verdict: render::render_verdict(&tree),
+                document: document,
+                tree: tree
+            };
+            Template::render("entry", &display)
         }
-        StepData::Attestation(ref attest) => {
-            let result = match *attest {
-                Attestation::Unknown { ref tag, ref data } => format!("Unknown attestation <b>{}</b>/<b>{}</b>", Hexed(tag), Hexed(data)),
-                Attestation::Pending { ref uri } => format!("Pending attestation: server <b>{}</b>", uri),
-                Attestation::Bitcoin { height } => {
-                    let root: Vec<u8> = prev_data.iter().rev().map(|x| *x).collect();
-                    format!("Merkle root <b>{}</b> of Bitcoin block <b>{}</b>", Hexed(&root), height)
-                }
+        Err(e) => error_page("View Timestamp", format!("{}", e))
+    }
+}
+
+// Every path through the timestamp as a separate proof
+#[get("/view/<id>/paths?<leaf>")]
+fn view_paths(id: DocId, leaf: Option<usize>, chain: State<Chain>, limits: State<Limits>) -> Template {
+    match cache::load(&cache::resolve(&id)) {
+        Ok(dtf) => {
+            let start_digest = &dtf.timestamp.start_digest;
+            let paths = match render::render_paths(&dtf.timestamp.first_step, start_digest, chain.source(), &limits, leaf) {
+                Ok(paths) => paths,
+                Err(e) => return error_page("View Timestamp", format!("Cannot display timestamp: {}", e))
             };
-            vec.push(DisplayedStep {
-                prefix: prefix.clone(),
-                result: result,
-                reason: "Attestation".to_owned(),
-                class: "step_attest"
-            });
+            let display = DisplayedPaths {
+                id: cache::doc_id(&dtf),
+                title: "Paths".to_owned(),
+                short_hash: format!("{}", Hexed(&start_digest[..start_digest.len().min(6)])),
+                start_hash: format!("{}", Hexed(start_digest)),
+                digest_type: format!("{}", dtf.digest_type),
+                selected: leaf,
+                paths: paths
+            };
+            Template::render("paths", &display)
         }
+        Err(e) => error_page("View Timestamp", format!("{}", e))
     }
 }
 
-// File viewer
-#[get("/view/<file..>")]
-fn view(file: PathBuf) -> Template {
-    match fs::File::open(Path::new("cache/").join(file)) {
-        Ok(fh) => {
-            match ots::DetachedTimestampFile::from_reader(fh) {
-                Ok(dtf) => {
-                    let mut steps = vec![];
-                    render_steps(&dtf.timestamp.first_step, &mut steps, &dtf.timestamp.start_digest, "".to_string());
-                    let display = DisplayedTimestamp {
-                        id: doc_id(&dtf),
-                        title: format!("Timestamp of <tt>{:?}</tt>", Hexed(&dtf.timestamp.start_digest[0..6])),
-                        start_hash: format!("{}", Hexed(&dtf.timestamp.start_digest)),
-                        digest_type: format!("{}", dtf.digest_type),
-                        steps: steps
-                    };
-                    Template::render("entry", &display)
-                }
-                Err(e) => {
-                    let mut context = HashMap::new();
-                    context.insert("title", "View Timestamp".to_owned());
-                    context.insert("error", format!("{}", e));
-                    Template::render("error", &context)
-                }
+// Graph of the timestamp tree
+#[get("/view/<id>/graph.svg")]
+fn view_graph(id: DocId, chain: State<Chain>, limits: State<Limits>) -> Result<content::Content<String>, Template> {
+    match cache::load(&cache::resolve(&id)) {
+        Ok(dtf) => {
+            match graph::render_graph(&dtf.timestamp.first_step, &dtf.timestamp.start_digest, chain.source(), &limits) {
+                Ok(svg) => Ok(content::Content(ContentType::SVG, svg)),
+                Err(e) => Err(error_page("View Timestamp", format!("Cannot draw timestamp: {}", e)))
             }
         }
-        Err(e) => {
-            let mut context = HashMap::new();
-            context.insert("title", "View Timestamp".to_owned());
-            context.insert("error", format!("{}", e));
-            Template::render("error", &context)
-        }
+        Err(e) => Err(error_page("View Timestamp", format!("{}", e)))
     }
 }
 
 // Download
-#[get("/download/<file..>")]
-fn download(file: PathBuf) -> Option<content::Content<NamedFile>> {
+#[get("/download/<id>")]
+fn download(id: DocId) -> Option<content::Content<NamedFile>> {
     let octet_stream: ContentType = ContentType::new("application", "octet-stream");
-    if let Ok(nf) = NamedFile::open(Path::new("cache/").join(file)) {
+    if let Ok(nf) = NamedFile::open(cache::path(&cache::resolve(&id))) {
         Some(content::Content(octet_stream, nf))
     } else {
         None
@@ -192,68 +253,215 @@ fn download(file: PathBuf) -> Option<content::Content<NamedFile>> {
 }
 
 
-fn doc_id_hash_recurse(step: &Step, hasher: &mut Sha256) {
-    hasher.input(&step.output);
-    for next in step.next.iter() {
-        doc_id_hash_recurse(next, hasher);
+// Upgrade handler
+#[post("/upgrade/<id>")]
+fn upgrade(id: DocId, calendars: State<Calendars>, cache: State<Cache>) -> Result<Redirect, Template> {
+    match calendars.upgrade_cached(&cache, &id) {
+        Ok(ref response) if response.upgraded => Ok(Redirect::to(format!("/view/{}", response.id))),
+        Ok(response) => {
+            let reasons: Vec<String> = response.attempts.iter().map(|attempt| {
+                match attempt.outcome {
+                    calendar::Outcome::Upgraded => format!("{}: upgraded", attempt.uri),
+                    calendar::Outcome::Pending => format!("{}: not yet committed to Bitcoin", attempt.uri),
+                    calendar::Outcome::NotWhitelisted => format!("{}: not a known calendar server", attempt.uri),
+                    calendar::Outcome::Failed(ref e) => format!("{}: {}", attempt.uri, e)
+                }
+            }).collect();
+            Err(error_page("Upgrade Timestamp", format!("No attestations could be upgraded. {}", reasons.join("; "))))
+        }
+        Err(e) => Err(error_page("Upgrade Timestamp", format!("{}", e)))
     }
 }
 
-/// Compute a unique filename for this timestamp
-fn doc_id(dtf: &ots::DetachedTimestampFile) -> String {
-    let mut output = [0; 32];
-    let mut hasher = Sha256::new();
-    hasher.input(&dtf.timestamp.start_digest);
-    doc_id_hash_recurse(&dtf.timestamp.first_step, &mut hasher);
-    hasher.result(&mut output);
-    format!("{}", Hexed(&output))
+/// Load a cached timestamp by an ID typed in by the user
+fn load_by_id(id: &str) -> Result<DetachedTimestampFile, String> {
+    let doc_id = match DocId::from_hex(id.trim()) {
+        Some(doc_id) => doc_id,
+        None => return Err(format!("{} is not a timestamp ID", id))
+    };
+    cache::load(&cache::resolve(&doc_id)).map_err(|e| format!("Cannot load {}: {}", doc_id, e))
 }
 
-// Upload handler
-#[post("/upload", data="<ots>")]
-fn upload(content_type: &ContentType, ots: Data) -> Redirect {
-    let options = MultipartFormDataOptions::with_multipart_form_data_fields(
-        vec![MultipartFormDataField::file("file")]
-    );
-    let multipart_form_data = MultipartFormData::parse(content_type, ots, options).unwrap();
-    let filepath = match multipart_form_data.files.get("file") {
-        Some(ref file) => &file[0].path,
-        None => {
-            println!("No file provided.");
-            return Redirect::to("/");
+/// List every stored timestamp of any of the given document digests
+fn search_page(query: String, digests: Vec<(DigestType, Vec<u8>)>) -> Template {
+    let mut results: Vec<SearchResult> = vec![];
+    for (digest_type, digest) in digests {
+        let ids = match cache::find_by_digest(&digest_type, &digest) {
+            Ok(ids) => ids,
+            Err(e) => return error_page("Find Timestamps", format!("Failed to search the index: {}", e))
+        };
+        for id in ids {
+            // Several stored IDs may have been upgraded to the same one
+            let id = cache::resolve(&id);
+            if results.iter().any(|result| result.id == id) {
+                continue;
+            }
+            if let Ok(dtf) = cache::load(&id) {
+                results.push(SearchResult {
+                    id: id,
+                    digest_type: format!("{}", dtf.digest_type),
+                    has_bitcoin: calendar::has_bitcoin(&dtf.timestamp.first_step),
+                    has_pending: calendar::has_pending(&dtf.timestamp.first_step)
+                });
+            }
         }
+    }
+    Template::render("search", &DisplayedSearch {
+        title: "Find Timestamps".to_owned(),
+        query: query,
+        results: results
+    })
+}
+
+// Search by document digest
+#[get("/digest/<digest>")]
+fn search_digest(digest: String) -> Template {
+    let bytes = match hex::decode(&digest) {
+        Ok(bytes) => bytes,
+        Err(_) => return error_page("Find Timestamps", "The document digest must be given in hex".to_owned())
     };
-    let fh = match fs::File::open(filepath) {
-        Ok(fh) => fh,
-        Err(e) => {
-            println!("Failed to open uploaded file: {}", e);
-            return Redirect::to("/");
+    let digests = cache::DIGEST_TYPES.iter().map(|digest_type| (digest_type.clone(), bytes.clone())).collect();
+    search_page(format!("digest {}", Hexed(&bytes)), digests)
+}
+
+// Search box, which may be given either a digest or the document itself
+#[post("/digest", data="<document>")]
+fn search_document(document: Result<MultipartStream, upload::Error>) -> Result<Template, status::Custom<Template>> {
+    let form = document.map_err(|e| e.page("Find Timestamps"))?;
+    let mut digests = vec![];
+    for digest_type in cache::DIGEST_TYPES.iter() {
+        match form.document_digest(digest_type) {
+            Ok(Some(digest)) => digests.push((digest_type.clone(), digest)),
+            Ok(None) => return Err(upload::Error::MissingField("document or digest").page("Find Timestamps")),
+            Err(e) => return Err(e.page("Find Timestamps"))
         }
+    }
+    let query = if form.document.is_some() { "your document".to_owned() } else { "your digest".to_owned() };
+    Ok(search_page(query, digests))
+}
+
+// Comparison of two cached timestamps
+#[get("/compare?<first>&<second>")]
+fn compare_ids(first: Option<String>, second: Option<String>) -> Template {
+    let (first, second) = match (first, second) {
+        (Some(ref first), Some(ref second)) if !first.is_empty() && !second.is_empty() => (first.clone(), second.clone()),
+        (first, second) => return Template::render("compare", &DisplayedComparison {
+            title: "Compare Timestamps".to_owned(),
+            first: first.unwrap_or_default(),
+            second: second.unwrap_or_default(),
+            comparison: None
+        })
     };
+    let first_dtf = match load_by_id(&first) {
+        Ok(dtf) => dtf,
+        Err(e) => return error_page("Compare Timestamps", e)
+    };
+    let second_dtf = match load_by_id(&second) {
+        Ok(dtf) => dtf,
+        Err(e) => return error_page("Compare Timestamps", e)
+    };
+    match merge::compare(first_dtf, second_dtf) {
+        Ok(comparison) => Template::render("compare", &DisplayedComparison {
+            title: "Compare Timestamps".to_owned(),
+            first: first,
+            second: second,
+            comparison: Some(comparison)
+        }),
+        Err(e) => error_page("Compare Timestamps", format!("Cannot compare timestamps: {}", e))
+    }
+}
 
-    match ots::DetachedTimestampFile::from_reader(fh) {
-        Ok(dtf) => {
-            let id = doc_id(&dtf);
-            match fs::File::create(Path::new("cache/").join(&id)) {
-                Ok(fh) => {
-                    if let Err(e) = dtf.to_writer(fh) {
-                        println!("Filed to write timestamp: {}", e);
-                        Redirect::to("/")
-                    } else {
-                        Redirect::to(format!("/view/{}", id))
-                    }
-                }
-                Err(e) => {
-                    println!("Filed to open {}: {}", id, e);
-                    Redirect::to("/")
-                }
-            }
-        }
-        Err(e) => {
-            // TODO somehow meaningfully show the error
-            println!("Filed to parse timestamp: {}", e);
-            Redirect::to("/")
+// Comparison of two uploaded timestamps
+#[post("/compare", data="<ots>")]
+fn compare_upload(ots: Result<MultipartStream, upload::Error>) -> Result<Template, status::Custom<Template>> {
+    compare_files(ots).map_err(|e| e.page("Compare Timestamps"))
+}
+
+/// Compare the two timestamp files of an upload
+fn compare_files(ots: Result<MultipartStream, upload::Error>) -> Result<Template, upload::Error> {
+    let form = ots?;
+    if form.files.len() != 2 {
+        return Err(upload::Error::MissingField("pair of timestamp files to compare"));
+    }
+    let first = upload::parse(&form.files[0][..])?;
+    let second = upload::parse(&form.files[1][..])?;
+    let comparison = merge::compare(first, second).map_err(upload::Error::Compare)?;
+    Ok(Template::render("compare", &DisplayedComparison {
+        title: "Compare Timestamps".to_owned(),
+        first: "the first uploaded file".to_owned(),
+        second: "the second uploaded file".to_owned(),
+        comparison: Some(comparison)
+    }))
+}
+
+// Prune handler
+#[post("/prune/<id>", data="<options>")]
+fn prune_timestamp(id: DocId, options: Form<PruneOptions>, chain: State<Chain>, limits: State<Limits>) -> Result<Redirect, Template> {
+    let preference = match &*options.by {
+        "shortest" => prune::Preference::Shortest,
+        "earliest" => prune::Preference::Earliest,
+        _ => return Err(error_page("Prune Timestamp", "Unknown choice of path".to_owned()))
+    };
+    let dtf = match cache::load(&cache::resolve(&id)) {
+        Ok(dtf) => dtf,
+        Err(e) => return Err(error_page("Prune Timestamp", format!("{}", e)))
+    };
+    match prune::prune(&dtf, chain.source(), &limits, preference).map(|pruned| cache::store(&pruned)) {
+        Ok(Ok(new_id)) => Ok(Redirect::to(format!("/view/{}", new_id))),
+        Ok(Err(e)) => Err(error_page("Prune Timestamp", format!("Failed to store pruned timestamp: {}", e))),
+        Err(e) => Err(error_page("Prune Timestamp", format!("Cannot prune timestamp: {}", e)))
+    }
+}
+
+// Upload handler
+#[post("/upload", data="<ots>")]
+fn upload(ots: Result<MultipartStream, upload::Error>) -> Result<Redirect, upload::Error> {
+    let form = ots?;
+    let dtf = match form.files.first() {
+        Some(data) => upload::parse(&data[..])?,
+        None => return Err(upload::Error::MissingField("timestamp file"))
+    };
+    let id = cache::store(&dtf)?;
+    match form.document_digest(&dtf.digest_type)? {
+        Some(digest) => Ok(Redirect::to(format!("/view/{}?digest={}", id, Hexed(&digest)))),
+        None => Ok(Redirect::to(format!("/view/{}", id)))
+    }
+}
+
+// Merge handler
+#[post("/merge", data="<ots>")]
+fn merge_upload(ots: Result<MultipartStream, upload::Error>, limits: State<Limits>) -> Result<Redirect, upload::Error> {
+    let form = ots?;
+    if form.files.len() < 2 {
+        return Err(upload::Error::MissingField("second timestamp file to merge"));
+    }
+    let mut files = Vec::with_capacity(form.files.len());
+    for data in &form.files {
+        files.push(upload::parse(&data[..])?);
+    }
+    let dtf = merge::merge(files)?;
+    // Each input was small, but together they need not be
+    tree::check(&dtf.timestamp.first_step, &dtf.timestamp.start_digest, &limits).map_err(upload::Error::Limits)?;
+    let id = cache::store(&dtf)?;
+    Ok(Redirect::to(format!("/view/{}", id)))
+}
+
+// Document verification handler
+#[post("/verify/<id>", data="<document>")]
+fn verify(id: DocId, document: Result<MultipartStream, upload::Error>) -> Result<Redirect, status::Custom<Template>> {
+    let form = document.map_err(|e| e.page("Verify Document"))?;
+    let id = cache::resolve(&id);
+    let dtf = match cache::load(&id) {
+        Ok(dtf) => dtf,
+        Err(cache::Error::Io(ref e)) if e.kind() == io::ErrorKind::NotFound => {
+            return Err(status::Custom(Status::NotFound, error_page("Verify Document", "No such timestamp".to_owned())));
         }
+        Err(e) => return Err(status::Custom(Status::InternalServerError, error_page("Verify Document", format!("{}", e))))
+    };
+    match form.document_digest(&dtf.digest_type) {
+        Ok(Some(digest)) => Ok(Redirect::to(format!("/view/{}?digest={}", id, Hexed(&digest)))),
+        Ok(None) => Err(upload::Error::MissingField("document or digest").page("Verify Document")),
+        Err(e) => Err(e.page("Verify Document"))
     }
 }
 
@@ -271,10 +479,111 @@ fn index() -> Template {
     Template::render("index", &context)
 }
 
+/// Set up the block header source described by the Rocket configuration
+fn load_chain(config: &Config) -> Result<Chain, String> {
+    let header_store = config.get_str("header_store").ok();
+    let bitcoind_url = config.get_str("bitcoind_url").ok();
+    match (header_store, bitcoind_url) {
+        (Some(_), Some(_)) => {
+            Err("only one of header_store and bitcoind_url may be set".to_owned())
+        }
+        (Some(path), None) => {
+            let store = chain::HeaderStore::load(path)
+                .map_err(|e| format!("failed to load header store {}: {}", path, e))?;
+            println!("Loaded {} block headers from {}", store.len(), path);
+            Ok(Chain(Some(Box::new(store))))
+        }
+        (None, Some(url)) => {
+            let auth = match (config.get_str("bitcoind_cookie"), config.get_str("bitcoind_user")) {
+                (Ok(cookie), _) => bitcoind::Auth::Cookie(PathBuf::from(cookie)),
+                (Err(_), Ok(user)) => {
+                    let pass = config.get_str("bitcoind_pass").unwrap_or("");
+                    bitcoind::Auth::UserPass(user.to_owned(), pass.to_owned())
+                }
+                (Err(_), Err(_)) => bitcoind::Auth::None
+            };
+            let timeout = config.get_int("bitcoind_timeout").unwrap_or(10);
+            let rpc = bitcoind::Bitcoind::new(url.to_owned(), auth, Duration::from_secs(timeout as u64))
+                .map_err(|e| format!("failed to set up bitcoind client: {}", e))?;
+            println!("Checking Bitcoin attestations against bitcoind at {}", url);
+            Ok(Chain(Some(Box::new(rpc))))
+        }
+        (None, None) => Ok(Chain(None))
+    }
+}
+
+/// Read the tree limits from the Rocket configuration
+fn load_limits(config: &Config) -> Limits {
+    let defaults = Limits::default();
+    Limits {
+        max_depth: config.get_int("max_tree_depth").map(|n| n as usize).unwrap_or(defaults.max_depth),
+        max_steps: config.get_int("max_tree_steps").map(|n| n as usize).unwrap_or(defaults.max_steps)
+    }
+}
+
+/// Set up a calendar client as described by the Rocket configuration
+fn load_calendars(config: &Config) -> Result<Calendars, String> {
+    let whitelist = match config.get_str("calendar_whitelist") {
+        Ok(list) => list.split(',').map(|s| s.trim().to_owned()).filter(|s| !s.is_empty()).collect(),
+        Err(_) => calendar::DEFAULT_WHITELIST.iter().map(|s| s.to_string()).collect()
+    };
+    let timeout = config.get_int("calendar_timeout").unwrap_or(10);
+    Calendars::new(whitelist, Duration::from_secs(timeout as u64), load_limits(config)).map_err(|e| e.to_string())
+}
+
 fn main() {
     rocket::ignite()
         .attach(Template::fairing())
-        .mount("/", routes![index, files, upload, download, view])
+        .manage(Cache::new(cache::CACHE_DIR))
+        .attach(AdHoc::on_attach("Tree limits", |rocket| {
+            let limits = load_limits(rocket.config());
+            Ok(rocket.manage(limits))
+        }))
+        .attach(AdHoc::on_attach("Block header source", |rocket| {
+            match load_chain(rocket.config()) {
+                Ok(chain) => Ok(rocket.manage(chain)),
+                Err(e) => {
+                    println!("Failed to set up block header source: {}", e);
+                    Err(rocket)
+                }
+            }
+        }))
+        .attach(AdHoc::on_attach("Calendar client", |rocket| {
+            match load_calendars(rocket.config()) {
+                Ok(calendars) => Ok(rocket.manage(calendars)),
+                Err(e) => {
+                    println!("Failed to set up calendar client: {}", e);
+                    Err(rocket)
+                }
+            }
+        }))
+        .attach(AdHoc::on_launch("Cache migration", |_| {
+            // Index first, so the index also lists IDs about to be moved,
+            // which stay resolvable through their links
+            match cache::migrate_digest_index() {
+                Ok(0) => {}
+                Ok(count) => println!("Indexed {} cached timestamps by document digest", count),
+                Err(e) => println!("Failed to index cached timestamps: {}", e)
+            }
+            match cache::migrate_doc_ids() {
+                Ok(0) => {}
+                Ok(count) => println!("Moved {} cached timestamps to their current IDs", count),
+                Err(e) => println!("Failed to move cached timestamps to their current IDs: {}", e)
+            }
+        }))
+        .attach(AdHoc::on_launch("Upgrade scheduler", |rocket| {
+            let interval = rocket.config().get_int("upgrade_interval").unwrap_or(600);
+            if interval <= 0 {
+                return;
+            }
+            match load_calendars(rocket.config()) {
+                Ok(calendars) => {
+                    scheduler::Scheduler::new(Cache::new(cache::CACHE_DIR), calendars, Duration::from_secs(interval as u64)).spawn();
+                }
+                Err(e) => println!("Failed to start upgrade scheduler: {}", e)
+            }
+        }))
+        .mount("/", routes![index, files, upload, merge_upload, verify, download, view, view_paths, view_graph, upgrade, prune_timestamp, compare_ids, compare_upload, search_digest, search_document, api::view, api::inspect, api::create, api::upgrade])
         .launch();
 }
 
@@ -0,0 +1,441 @@
+// OpenTimestamps Viewer
+// Written in 2017 by
+//   Andrew Poelstra <rust-ots@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Merge
+//!
+//! Combining several timestamps of the same document into one, sharing
+//! the steps they have in common, and comparing two such timestamps. Like
+//! the rest of the tree handling this uses explicit stacks rather than
+//! recursion, since timestamps come from untrusted uploads.
+//!
+
+use std::{error, fmt};
+
+use ots::DetachedTimestampFile;
+use ots::attestation::Attestation;
+use ots::hex::Hexed;
+use ots::timestamp::{Step, StepData, Timestamp};
+
+use tree;
+
+/// Errors encountered while merging timestamps
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Error {
+    /// No timestamps were given
+    Empty,
+    /// The timestamps use different hash functions for the document
+    DigestTypeMismatch,
+    /// The timestamps are of different documents
+    DigestMismatch
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Empty => f.write_str("no timestamps to merge"),
+            Error::DigestTypeMismatch => f.write_str("timestamps use different hash functions for the document"),
+            Error::DigestMismatch => f.write_str("timestamps are of different documents")
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::Empty => "no timestamps",
+            Error::DigestTypeMismatch => "digest type mismatch",
+            Error::DigestMismatch => "digest mismatch"
+        }
+    }
+}
+
+/// Marks steps of the first timestamp in a comparison, or of any
+/// timestamp in a merge
+const FIRST: u8 = 1;
+/// Marks steps of the second timestamp in a comparison
+const SECOND: u8 = 2;
+
+/// A step of the merged tree, before it is assembled. Forks are not
+/// represented; a node with several children becomes a step followed
+/// by a fork.
+struct Node {
+    data: StepData,
+    output: Vec<u8>,
+    children: Vec<usize>,
+    /// Bitmask of the timestamps this step appears in, for comparisons
+    sources: u8
+}
+
+/// Add the steps of a tree to the merged tree, under the root node,
+/// marking them as coming from `source`
+fn insert(nodes: &mut Vec<Node>, first_step: Step, source: u8) {
+    nodes[0].sources |= source;
+    let mut stack = vec![(first_step, 0)];
+    while let Some((step, parent)) = stack.pop() {
+        let Step { data, output, next } = step;
+        if let StepData::Fork = data {
+            // The branches of a fork all continue from the same data, so
+            // they simply become further children of the parent. Push in
+            // reverse so that the first branch is added first.
+            for next in next.into_iter().rev() {
+                stack.push((next, parent));
+            }
+            continue;
+        }
+
+        let existing = nodes[parent].children.iter().map(|&n| n).find(|&n| nodes[n].data == data);
+        let node = match existing {
+            Some(n) => n,
+            None => {
+                nodes.push(Node {
+                    data: data,
+                    output: output,
+                    children: vec![],
+                    sources: 0
+                });
+                let n = nodes.len() - 1;
+                nodes[parent].children.push(n);
+                n
+            }
+        };
+        nodes[node].sources |= source;
+        for next in next {
+            stack.push((next, node));
+        }
+    }
+}
+
+/// Start a merged tree with a root node standing for the start digest,
+/// which is never output itself
+fn new_tree(start_digest: &[u8]) -> Vec<Node> {
+    vec![Node {
+        data: StepData::Fork,
+        output: start_digest.to_vec(),
+        children: vec![],
+        sources: 0
+    }]
+}
+
+/// Merge timestamps of the same document into a single timestamp, failing
+/// if they are not all of the same digest
+pub fn merge(files: Vec<DetachedTimestampFile>) -> Result<DetachedTimestampFile, Error> {
+    let mut iter = files.into_iter();
+    let first = match iter.next() {
+        Some(first) => first,
+        None => return Err(Error::Empty)
+    };
+    let digest_type = first.digest_type;
+    let start_digest = first.timestamp.start_digest;
+
+    let mut nodes = new_tree(&start_digest);
+    insert(&mut nodes, first.timestamp.first_step, FIRST);
+    for dtf in iter {
+        if dtf.digest_type != digest_type {
+            return Err(Error::DigestTypeMismatch);
+        }
+        if dtf.timestamp.start_digest != start_digest {
+            return Err(Error::DigestMismatch);
+        }
+        insert(&mut nodes, dtf.timestamp.first_step, FIRST);
+    }
+
+    // Children always come after their parents
+    let mut parents: Vec<Option<usize>> = nodes.iter().map(|_| None).collect();
+    for (n, node) in nodes.iter().enumerate() {
+        for &child in &node.children {
+            parents[child] = Some(n);
+        }
+    }
+    let root = tree::assemble(nodes.into_iter().zip(parents).collect(), |node, children| {
+        let next = if children.len() > 1 {
+            vec![Step {
+                data: StepData::Fork,
+                output: node.output.clone(),
+                next: children
+            }]
+        } else {
+            children
+        };
+        Step {
+            data: node.data,
+            output: node.output,
+            next: next
+        }
+    });
+
+    let first_step = match root {
+        Some(root) => root.next.into_iter().next(),
+        None => None
+    };
+    let first_step = match first_step {
+        Some(step) => step,
+        None => Step {
+            data: StepData::Fork,
+            output: start_digest.clone(),
+            next: vec![]
+        }
+    };
+    Ok(DetachedTimestampFile {
+        digest_type: digest_type,
+        timestamp: Timestamp {
+            start_digest: start_digest,
+            first_step: first_step
+        }
+    })
+}
+
+/// A row of a comparison between two timestamps
+#[derive(Debug, Serialize)]
+pub struct DiffRow {
+    /// Number of forks between the start digest and this row
+    indent: usize,
+    /// Which timestamps the steps of this row appear in
+    side: &'static str,
+    text: String,
+    class: &'static str
+}
+
+/// Where two timestamps of the same document agree and diverge
+#[derive(Debug, Serialize)]
+pub struct Comparison {
+    /// Whether the two timestamps have exactly the same steps
+    identical: bool,
+    rows: Vec<DiffRow>,
+    /// Attestations only in the second timestamp
+    added: Vec<String>,
+    /// Attestations only in the first timestamp
+    removed: Vec<String>
+}
+
+/// Describe an attestation for a comparison
+fn describe_attestation(attest: &Attestation) -> String {
+    match *attest {
+        Attestation::Bitcoin { height } => format!("Bitcoin block {}", height),
+        Attestation::Pending { ref uri } => format!("Pending at {}", uri),
+        Attestation::Unknown { ref tag, .. } => format!("Unknown attestation {}", Hexed(tag))
+    }
+}
+
+/// Name the timestamps a set of steps appears in
+fn side(sources: u8) -> (&'static str, &'static str) {
+    match sources {
+        FIRST => ("first only", "diff_first"),
+        SECOND => ("second only", "diff_second"),
+        _ => ("both", "diff_both")
+    }
+}
+
+/// Compare two timestamps of the same document, failing if they are not
+/// of the same digest. Runs of operations which appear in the same
+/// timestamps are shown as a single row.
+pub fn compare(first: DetachedTimestampFile, second: DetachedTimestampFile) -> Result<Comparison, Error> {
+    if second.digest_type != first.digest_type {
+        return Err(Error::DigestTypeMismatch);
+    }
+    if second.timestamp.start_digest != first.timestamp.start_digest {
+        return Err(Error::DigestMismatch);
+    }
+    let mut nodes = new_tree(&first.timestamp.start_digest);
+    insert(&mut nodes, first.timestamp.first_step, FIRST);
+    insert(&mut nodes, second.timestamp.first_step, SECOND);
+
+    let mut rows = vec![];
+    let mut added = vec![];
+    let mut removed = vec![];
+    let base = if nodes[0].children.len() > 1 {
+        rows.push(DiffRow {
+            indent: 0,
+            side: "both",
+            text: format!("Paths diverge into {}", nodes[0].children.len()),
+            class: "diff_fork"
+        });
+        1
+    } else {
+        0
+    };
+    let mut stack: Vec<(usize, usize)> = nodes[0].children.iter().rev().map(|&n| (n, base)).collect();
+    while let Some((start, indent)) = stack.pop() {
+        let sources = nodes[start].sources;
+        let (side_name, class) = side(sources);
+
+        // Follow a run of operations in the same timestamps
+        let mut end = start;
+        let text = match nodes[start].data {
+            StepData::Attestation(ref attest) => {
+                let description = describe_attestation(attest);
+                match sources {
+                    FIRST => removed.push(description.clone()),
+                    SECOND => added.push(description.clone()),
+                    _ => {}
+                }
+                description
+            }
+            _ => {
+                let mut count = 1;
+                while nodes[end].children.len() == 1 {
+                    let next = nodes[end].children[0];
+                    match nodes[next].data {
+                        StepData::Op(_) if nodes[next].sources == sources => {
+                            end = next;
+                            count += 1;
+                        }
+                        _ => break
+                    }
+                }
+                format!("{} operation{}, ending in {}", count, if count == 1 { "" } else { "s" }, Hexed(&nodes[end].output))
+            }
+        };
+        rows.push(DiffRow {
+            indent: indent,
+            side: side_name,
+            text: text,
+            class: class
+        });
+
+        let children = &nodes[end].children;
+        if children.len() > 1 {
+            rows.push(DiffRow {
+                indent: indent,
+                side: side_name,
+                text: format!("Paths diverge into {}", children.len()),
+                class: "diff_fork"
+            });
+            for &child in children.iter().rev() {
+                stack.push((child, indent + 1));
+            }
+        } else {
+            for &child in children {
+                stack.push((child, indent));
+            }
+        }
+    }
+
+    Ok(Comparison {
+        identical: nodes.iter().all(|node| node.sources == FIRST | SECOND),
+        rows: rows,
+        added: added,
+        removed: removed
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use ots::DetachedTimestampFile;
+    use ots::op::Op;
+    use ots::ser::DigestType;
+
+    use testutil::{bitcoin, file, fork, op, serialized};
+
+    use super::{compare, merge, Error};
+
+    /// Append 1 then attest at height 1
+    fn short() -> DetachedTimestampFile {
+        file(op(Op::Append(vec![1]), bitcoin(1)))
+    }
+
+    /// Append 1, append 2, then attest at height 2
+    fn long() -> DetachedTimestampFile {
+        file(op(Op::Append(vec![1]), op(Op::Append(vec![2]), bitcoin(2))))
+    }
+
+    #[test]
+    fn duplicates() {
+        let merged = merge(vec![short(), short(), short()]).unwrap();
+        assert_eq!(serialized(&merged), serialized(&short()));
+        let merged = merge(vec![long()]).unwrap();
+        assert_eq!(serialized(&merged), serialized(&long()));
+    }
+
+    #[test]
+    fn shared_prefix() {
+        let merged = merge(vec![short(), long()]).unwrap();
+        let expected = file(op(Op::Append(vec![1]), fork(vec![
+            bitcoin(1),
+            op(Op::Append(vec![2]), bitcoin(2))
+        ])));
+        assert_eq!(serialized(&merged), serialized(&expected));
+    }
+
+    #[test]
+    fn fork_flattening() {
+        // A fork at the start of a timestamp does not become a step of
+        // its own, its branches join those of the other timestamps
+        let forked = file(fork(vec![
+            op(Op::Append(vec![1]), bitcoin(1)),
+            op(Op::Append(vec![3]), bitcoin(3))
+        ]));
+        let merged = merge(vec![short(), forked]).unwrap();
+        let expected = file(fork(vec![
+            op(Op::Append(vec![1]), bitcoin(1)),
+            op(Op::Append(vec![3]), bitcoin(3))
+        ]));
+        assert_eq!(serialized(&merged), serialized(&expected));
+
+        // Nor does a fork straight after another
+        let nested = file(fork(vec![
+            fork(vec![op(Op::Append(vec![3]), bitcoin(3))]),
+            op(Op::Append(vec![1]), bitcoin(1))
+        ]));
+        let merged = merge(vec![nested]).unwrap();
+        let expected = file(fork(vec![
+            op(Op::Append(vec![3]), bitcoin(3)),
+            op(Op::Append(vec![1]), bitcoin(1))
+        ]));
+        assert_eq!(serialized(&merged), serialized(&expected));
+    }
+
+    #[test]
+    fn mismatches() {
+        assert_eq!(merge(vec![]).err(), Some(Error::Empty));
+
+        let mut sha1 = short();
+        sha1.digest_type = DigestType::Sha1;
+        assert_eq!(merge(vec![short(), sha1]).err(), Some(Error::DigestTypeMismatch));
+        let mut sha1 = short();
+        sha1.digest_type = DigestType::Sha1;
+        assert_eq!(compare(short(), sha1).err(), Some(Error::DigestTypeMismatch));
+
+        let mut other = short();
+        other.timestamp.start_digest = vec![0xbb; 32];
+        assert_eq!(merge(vec![short(), other]).err(), Some(Error::DigestMismatch));
+        let mut other = short();
+        other.timestamp.start_digest = vec![0xbb; 32];
+        assert_eq!(compare(short(), other).err(), Some(Error::DigestMismatch));
+    }
+
+    #[test]
+    fn round_trip() {
+        let merged = merge(vec![short(), long()]).unwrap();
+        let data = serialized(&merged);
+        let parsed = DetachedTimestampFile::from_reader(&data[..]).unwrap();
+        assert_eq!(serialized(&parsed), data);
+        // Merging the result again changes nothing
+        let remerged = merge(vec![parsed, long(), short()]).unwrap();
+        assert_eq!(serialized(&remerged), data);
+    }
+
+    #[test]
+    fn comparison() {
+        let same = compare(short(), short()).unwrap();
+        assert!(same.identical);
+        assert!(same.added.is_empty() && same.removed.is_empty());
+
+        let diff = compare(short(), long()).unwrap();
+        assert!(!diff.identical);
+        assert_eq!(diff.added, vec!["Bitcoin block 2".to_owned()]);
+        assert_eq!(diff.removed, vec!["Bitcoin block 1".to_owned()]);
+        let sides: Vec<&str> = diff.rows.iter().map(|row| row.side).collect();
+        assert_eq!(sides, vec!["both", "both", "first only", "second only", "second only"]);
+    }
+}
@@ -17,53 +17,273 @@
 //! Shim to allow streaming of multipart data
 //!
 
-use std::io::Read;
+use std::io::{self, Read};
 
-use multipart::server::{Multipart, MultipartData, ReadEntryResult};
+use hex;
+use multipart::server::Multipart;
+use ots::ser::DigestType;
 use rocket::{Request, Data, Outcome};
-use rocket::data::{self, FromData};
-use rocket::http::Status;
+use rocket::data::{self, FromDataSimple};
 
-const SIZE_LIMIT: u64 = 32768;
+use document::DocumentDigests;
+use upload::{self, Error, SIZE_LIMIT};
 
-/// A wrapper around a multipart stream
+/// Longest hex digest we will accept from the `digest` field
+const DIGEST_LIMIT: u64 = 1024;
+
+/// Most timestamp files we will accept in a single upload
+pub const MAX_FILES: usize = 16;
+
+/// An upload form, read directly from the request body. Timestamp files
+/// are capped at `SIZE_LIMIT` so are held in memory; the original document
+/// may be large so is only streamed through the hashers. Nothing is ever
+/// spooled to a temporary file.
 pub struct MultipartStream {
-    pub stream: Box<dyn Read>
+    /// The timestamp files, from any `file` fields, in order
+    pub files: Vec<Vec<u8>>,
+    /// Digests of the original document, from the `document` field
+    pub document: Option<DocumentDigests>,
+    /// A hex digest of the original document, from the `digest` field
+    pub digest: Option<String>
 }
 
-impl<'a> FromData<'a> for MultipartStream {
-    type Error = &'static str;
-    type Owned = &'static str;
-    type Borrowed = &'static str;
+impl MultipartStream {
+    /// The digest of the original document under `digest_type`, if the
+    /// user provided either the document or its digest
+    pub fn document_digest(&self, digest_type: &DigestType) -> Result<Option<Vec<u8>>, Error> {
+        if let Some(ref document) = self.document {
+            return Ok(Some(document.get(digest_type).to_vec()));
+        }
+        match self.digest {
+            Some(ref digest) => match hex::decode(digest) {
+                Ok(digest) => Ok(Some(digest)),
+                Err(_) => Err(Error::BadDigest)
+            },
+            None => Ok(None)
+        }
+    }
+}
 
-    fn from_data(request: &Request, data: Data) -> data::Outcome<Self, Self::Error> {
-        let ct = match request.headers().get_one("Content-Type") {
-            Some(ct) => ct,
-            None => { return Outcome::Failure((Status::BadRequest, "no Content-Type in request")); }
-        };
-        let idx = match ct.find("boundary=") {
-            Some(idx) => idx,
-            None => { return Outcome::Failure((Status::BadRequest, "no boundary= in Content-Type")); }
-        };
-        let boundary = &ct[(idx + "boundary=".len())..];
-
-        let mp = Multipart::with_body(data.open(), boundary);
-        if let ReadEntryResult::Entry(entry) = mp.into_entry() {
-            if entry.name == "file" {
-                if let MultipartData::File(file) = entry.data {
-                    Outcome::Success(MultipartStream {
-                        stream: Box::new(file.take(SIZE_LIMIT))
-                    })
-                } else {
-                    Outcome::Failure((Status::BadRequest, "malformed multipart data"))
+/// Failures reading the request body are down to the client, so are
+/// reported as malformed uploads rather than as our own I/O errors
+fn body_error(e: io::Error) -> Error {
+    Error::Multipart(e.to_string())
+}
+
+/// Read the contents of a `file` field, rejecting it as soon as it is
+/// seen not to be a timestamp or exceeds the size limit
+fn read_file_field<R: Read>(field: R) -> Result<Vec<u8>, Error> {
+    let mut stream = field.take(SIZE_LIMIT + 1);
+    let mut data = vec![0; upload::MAGIC.len()];
+    let mut filled = 0;
+    while filled < data.len() {
+        match stream.read(&mut data[filled..]).map_err(body_error)? {
+            0 => break,
+            n => filled += n
+        }
+    }
+    data.truncate(filled);
+    upload::check_magic(&data)?;
+
+    stream.read_to_end(&mut data).map_err(body_error)?;
+    if data.len() as u64 > SIZE_LIMIT {
+        return Err(Error::TooLarge(SIZE_LIMIT));
+    }
+    Ok(data)
+}
+
+/// Read every field we care about out of a multipart body, in whatever
+/// order they appear
+fn read_fields<R: Read>(body: R, boundary: &str) -> Result<MultipartStream, Error> {
+    let mut ret = MultipartStream {
+        files: vec![],
+        document: None,
+        digest: None
+    };
+    let mut multipart = Multipart::with_body(body, boundary);
+    while let Some(field) = multipart.read_entry().map_err(body_error)? {
+        // Browsers send an empty, unnamed file for file inputs left blank
+        let is_blank_file = field.headers.filename.as_ref().map(|f| f.is_empty()).unwrap_or(false);
+        match &*field.headers.name {
+            "file" if !is_blank_file => {
+                if ret.files.len() >= MAX_FILES {
+                    return Err(Error::Multipart(format!("no more than {} timestamp files may be uploaded at once", MAX_FILES)));
                 }
-            } else {
-                Outcome::Failure((Status::BadRequest, "malformed multipart data"))
+                ret.files.push(read_file_field(field.data)?);
             }
-        } else {
-            Outcome::Failure((Status::BadRequest, "missing multipart data"))
+            "document" if !is_blank_file => {
+                ret.document = Some(DocumentDigests::hash_reader(field.data).map_err(body_error)?);
+            }
+            "digest" => {
+                let mut digest = String::new();
+                field.data.take(DIGEST_LIMIT + 1).read_to_string(&mut digest).map_err(body_error)?;
+                if digest.len() as u64 > DIGEST_LIMIT {
+                    return Err(Error::BadDigest);
+                }
+                let digest = digest.trim();
+                if !digest.is_empty() {
+                    ret.digest = Some(digest.to_owned());
+                }
+            }
+            _ => {
+                // Skipping a field still reads it, so fields we do not use
+                // are held to the same limit as timestamp files
+                let skipped = io::copy(&mut field.data.take(SIZE_LIMIT + 1), &mut io::sink()).map_err(body_error)?;
+                if skipped > SIZE_LIMIT {
+                    return Err(Error::TooLarge(SIZE_LIMIT));
+                }
+            }
+        }
+    }
+    Ok(ret)
+}
+
+impl FromDataSimple for MultipartStream {
+    type Error = Error;
+
+    fn from_data(request: &Request, data: Data) -> data::Outcome<Self, Self::Error> {
+        let boundary = request.content_type()
+            .and_then(|ct| ct.params().find(|&(key, _)| key == "boundary").map(|(_, value)| value.to_owned()));
+        let boundary = match boundary {
+            Some(boundary) => boundary.trim_matches('"').to_owned(),
+            None => {
+                let err = Error::Multipart("no multipart boundary in Content-Type".to_owned());
+                return Outcome::Failure((err.status(), err));
+            }
+        };
+
+        match read_fields(data.open(), &boundary) {
+            Ok(form) => Outcome::Success(form),
+            Err(e) => Outcome::Failure((e.status(), e))
         }
     }
 }
 
 
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use ots::hex::Hexed;
+    use ots::ser::DigestType;
+
+    use testutil::{bitcoin, file, serialized};
+    use upload::{Error, MAGIC, SIZE_LIMIT};
+
+    use super::{read_fields, MultipartStream, DIGEST_LIMIT, MAX_FILES};
+
+    /// A multipart body with the given fields: each a name, a filename if
+    /// it is a file, and its contents
+    fn body(fields: &[(&str, Option<&str>, &[u8])]) -> Vec<u8> {
+        let mut body = vec![];
+        for &(name, filename, data) in fields {
+            write!(body, "--boundary\r\nContent-Disposition: form-data; name=\"{}\"", name).unwrap();
+            if let Some(filename) = filename {
+                write!(body, "; filename=\"{}\"\r\nContent-Type: application/octet-stream", filename).unwrap();
+            }
+            body.extend_from_slice(b"\r\n\r\n");
+            body.extend_from_slice(data);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(b"--boundary--\r\n");
+        body
+    }
+
+    fn read(fields: &[(&str, Option<&str>, &[u8])]) -> Result<MultipartStream, Error> {
+        read_fields(&body(fields)[..], "boundary")
+    }
+
+    /// A timestamp file padded out to `len` bytes
+    fn timestamp_file(len: usize) -> Vec<u8> {
+        let mut data = MAGIC.to_vec();
+        data.resize(len, 0);
+        data
+    }
+
+    #[test]
+    fn fields() {
+        let first = serialized(&file(bitcoin(100)));
+        let second = serialized(&file(bitcoin(200)));
+        let form = read(&[
+            ("file", Some("first.ots"), &first),
+            ("unused", None, b"ignored"),
+            // Left blank by the browser
+            ("document", Some(""), b""),
+            ("file", Some("second.ots"), &second),
+            ("file", Some(""), b""),
+            ("digest", None, b" 00ff \r\n")
+        ]).unwrap();
+        assert_eq!(form.files, vec![first, second]);
+        assert!(form.document.is_none());
+        assert_eq!(form.digest, Some("00ff".to_owned()));
+        assert_eq!(form.document_digest(&DigestType::Sha256).unwrap(), Some(vec![0x00, 0xff]));
+
+        // The document takes precedence over a typed-in digest
+        let form = read(&[("digest", None, b"00ff"), ("document", Some("hello.txt"), b"hello")]).unwrap();
+        assert_eq!(
+            form.document_digest(&DigestType::Sha256).unwrap().map(|d| format!("{}", Hexed(&d))),
+            Some("2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824".to_owned())
+        );
+
+        let form = read(&[("digest", None, b"not hex")]).unwrap();
+        match form.document_digest(&DigestType::Sha256) {
+            Err(Error::BadDigest) => {}
+            _ => panic!("accepted a digest which is not hex")
+        }
+    }
+
+    #[test]
+    fn magic() {
+        match read(&[("file", Some("a.txt"), b"This is not a timestamp file, though it is long enough")]) {
+            Err(Error::NotTimestamp) => {}
+            _ => panic!("accepted a file without the magic bytes")
+        }
+        match read(&[("file", Some("a.ots"), &MAGIC[..10])]) {
+            Err(Error::NotTimestamp) => {}
+            _ => panic!("accepted a file too short for the magic bytes")
+        }
+    }
+
+    #[test]
+    fn file_size() {
+        let limit = SIZE_LIMIT as usize;
+        assert_eq!(read(&[("file", Some("a.ots"), &timestamp_file(limit))]).unwrap().files[0].len(), limit);
+        match read(&[("file", Some("a.ots"), &timestamp_file(limit + 1))]) {
+            Err(Error::TooLarge(size)) => assert_eq!(size, SIZE_LIMIT),
+            _ => panic!("accepted an oversized file")
+        }
+    }
+
+    #[test]
+    fn file_count() {
+        let data = serialized(&file(bitcoin(100)));
+        let fields = vec![("file", Some("a.ots"), &data[..]); MAX_FILES + 1];
+        assert_eq!(read(&fields[..MAX_FILES]).unwrap().files.len(), MAX_FILES);
+        match read(&fields) {
+            Err(Error::Multipart(_)) => {}
+            _ => panic!("accepted too many files")
+        }
+    }
+
+    #[test]
+    fn digest_size() {
+        let limit = DIGEST_LIMIT as usize;
+        let digest = vec![b'a'; limit];
+        assert_eq!(read(&[("digest", None, &digest)]).unwrap().digest.map(|d| d.len()), Some(limit));
+        let digest = vec![b'a'; limit + 1];
+        match read(&[("digest", None, &digest)]) {
+            Err(Error::BadDigest) => {}
+            _ => panic!("accepted an oversized digest")
+        }
+    }
+
+    #[test]
+    fn skipped_size() {
+        let limit = SIZE_LIMIT as usize;
+        assert!(read(&[("unused", None, &vec![b'a'; limit])]).is_ok());
+        match read(&[("unused", Some("big.bin"), &vec![b'a'; limit + 1])]) {
+            Err(Error::TooLarge(size)) => assert_eq!(size, SIZE_LIMIT),
+            _ => panic!("read an oversized field we do not use")
+        }
+    }
+}
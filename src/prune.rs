@@ -0,0 +1,281 @@
+// OpenTimestamps Viewer
+// Written in 2017 by
+//   Andrew Poelstra <rust-ots@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Prune
+//!
+//! Reduction of a timestamp to a single path to a verified Bitcoin
+//! attestation, for archiving proofs without pending branches or
+//! calendar URIs
+//!
+
+use std::{error, fmt};
+
+use ots::DetachedTimestampFile;
+use ots::attestation::Attestation;
+use ots::timestamp::{Step, StepData, Timestamp};
+
+use chain::{self, HeaderSource, Verification};
+use tree::{self, Limits};
+
+/// Which path to keep when several lead to verified attestations
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Preference {
+    /// The path with the fewest operations
+    Shortest,
+    /// The path to the lowest block
+    Earliest
+}
+
+/// Errors encountered while pruning a timestamp
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Error {
+    /// There is no block header source to verify attestations with
+    NoHeaderSource,
+    /// No Bitcoin attestation in the timestamp could be verified
+    NoVerifiedAttestation,
+    /// The timestamp is malformed or too large
+    Tree(tree::Error)
+}
+
+impl From<tree::Error> for Error {
+    fn from(e: tree::Error) -> Error {
+        Error::Tree(e)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::NoHeaderSource => f.write_str("no block header source is configured to verify attestations with"),
+            Error::NoVerifiedAttestation => f.write_str("timestamp has no verified Bitcoin attestation"),
+            Error::Tree(ref e) => fmt::Display::fmt(e, f)
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::NoHeaderSource => "no block header source",
+            Error::NoVerifiedAttestation => "no verified Bitcoin attestation",
+            Error::Tree(_) => "malformed or oversized timestamp"
+        }
+    }
+}
+
+/// A verified Bitcoin attestation and how to reach it
+struct Candidate {
+    /// Number of operations between the start digest and the attestation
+    length: usize,
+    height: usize,
+    /// Index of the branch taken at each fork on the way
+    branch: Vec<usize>
+}
+
+/// Reduce a timestamp to the single path to a verified Bitcoin attestation
+/// which best matches `preference`, failing if there is none
+pub fn prune(dtf: &DetachedTimestampFile, chain: Option<&dyn HeaderSource>, limits: &Limits, preference: Preference) -> Result<DetachedTimestampFile, Error> {
+    let source = match chain {
+        Some(source) => source,
+        None => return Err(Error::NoHeaderSource)
+    };
+
+    let mut best: Option<Candidate> = None;
+    tree::walk(&dtf.timestamp.first_step, &dtf.timestamp.start_digest, limits, |visit| {
+        let height = match visit.step.data {
+            StepData::Attestation(Attestation::Bitcoin { height }) => height,
+            _ => return
+        };
+        match chain::verify(source, height, visit.input) {
+            Ok(Verification::Verified { .. }) => {}
+            _ => return
+        }
+        let candidate = Candidate {
+            length: visit.depth - visit.branch.len(),
+            height: height,
+            branch: visit.branch.clone()
+        };
+        let better = match best {
+            None => true,
+            Some(ref best) => match preference {
+                Preference::Shortest => (candidate.length, candidate.height) < (best.length, best.height),
+                Preference::Earliest => (candidate.height, candidate.length) < (best.height, best.length)
+            }
+        };
+        if better {
+            best = Some(candidate);
+        }
+    })?;
+    let best = match best {
+        Some(best) => best,
+        None => return Err(Error::NoVerifiedAttestation)
+    };
+
+    // Follow the chosen branches down to the attestation, which is the
+    // first attestation met since each path ends in exactly one
+    let mut ops = vec![];
+    let mut step = &dtf.timestamp.first_step;
+    let mut forks = best.branch.iter();
+    loop {
+        match step.data {
+            StepData::Fork => {
+                // The walk already checked that these indices exist
+                step = &step.next[*forks.next().unwrap_or(&0)];
+            }
+            StepData::Op(_) => {
+                ops.push(step);
+                step = &step.next[0];
+            }
+            StepData::Attestation(_) => break
+        }
+    }
+
+    // Rebuild the path from the attestation upward, without forks
+    let mut first_step = Step {
+        data: step.data.clone(),
+        output: step.output.clone(),
+        next: vec![]
+    };
+    for op in ops.into_iter().rev() {
+        first_step = Step {
+            data: op.data.clone(),
+            output: op.output.clone(),
+            next: vec![first_step]
+        };
+    }
+    Ok(DetachedTimestampFile {
+        digest_type: dtf.digest_type.clone(),
+        timestamp: Timestamp {
+            start_digest: dtf.timestamp.start_digest.clone(),
+            first_step: first_step
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use ots::DetachedTimestampFile;
+    use ots::attestation::Attestation;
+    use ots::op::Op;
+    use ots::timestamp::StepData;
+
+    use chain::{Error, Header, HeaderSource, HEADER_LEN};
+    use testutil::{bitcoin, file, fork, op, pending, serialized};
+    use tree::{self, Limits};
+
+    use super::{prune, Preference};
+
+    /// A header source knowing only the Merkle roots of its blocks, which
+    /// are all 0xee bytes unless given otherwise
+    struct Roots(Vec<(usize, Vec<u8>)>);
+
+    impl HeaderSource for Roots {
+        fn header_at(&self, height: usize) -> Result<Option<Header>, Error> {
+            if height >= 10 {
+                return Ok(None);
+            }
+            let mut data = vec![0xee; HEADER_LEN];
+            if let Some(&(_, ref root)) = self.0.iter().find(|&&(h, _)| h == height) {
+                data[36..68].copy_from_slice(root);
+            }
+            Ok(Header::from_bytes(&data))
+        }
+    }
+
+    /// A header source verifying the Bitcoin attestations of `dtf` at
+    /// each of `heights`
+    fn roots(dtf: &DetachedTimestampFile, heights: &[usize]) -> Roots {
+        let mut roots = vec![];
+        tree::walk(&dtf.timestamp.first_step, &dtf.timestamp.start_digest, &Limits::default(), |visit| {
+            if let StepData::Attestation(Attestation::Bitcoin { height }) = visit.step.data {
+                if heights.contains(&height) {
+                    roots.push((height, visit.input.to_vec()));
+                }
+            }
+        }).unwrap();
+        Roots(roots)
+    }
+
+    /// A timestamp with verified attestations at heights 5 (after three
+    /// operations), 7 (after one, below two forks) and 2 (after two), and
+    /// an unverified one at height 1 (after one)
+    fn timestamp() -> DetachedTimestampFile {
+        file(fork(vec![
+            op(Op::Append(vec![1]), op(Op::Sha256, op(Op::Sha256, bitcoin(5)))),
+            op(Op::Sha256, fork(vec![
+                pending("https://alice.btc.calendar.opentimestamps.org"),
+                bitcoin(7)
+            ])),
+            op(Op::Reverse, bitcoin(1)),
+            op(Op::Append(vec![6]), op(Op::Sha256, bitcoin(2)))
+        ]))
+    }
+
+    #[test]
+    fn shortest() {
+        let dtf = timestamp();
+        let pruned = prune(&dtf, Some(&roots(&dtf, &[5, 7, 2])), &Limits::default(), Preference::Shortest).unwrap();
+        // Both forks on the way are gone
+        assert_eq!(serialized(&pruned), serialized(&file(op(Op::Sha256, bitcoin(7)))));
+        let kept = &dtf.timestamp.first_step.next[1];
+        assert_eq!(pruned.timestamp.first_step.output, kept.output);
+        assert_eq!(pruned.timestamp.first_step.next[0].output, kept.output);
+    }
+
+    #[test]
+    fn earliest() {
+        let dtf = timestamp();
+        let pruned = prune(&dtf, Some(&roots(&dtf, &[5, 7, 2])), &Limits::default(), Preference::Earliest).unwrap();
+        let expected = file(op(Op::Append(vec![6]), op(Op::Sha256, bitcoin(2))));
+        assert_eq!(serialized(&pruned), serialized(&expected));
+        assert_eq!(pruned.timestamp.first_step.next[0].output, expected.timestamp.first_step.next[0].output);
+    }
+
+    #[test]
+    fn ties() {
+        // Equally short paths are decided by height, and vice versa, with
+        // the losing path of each tie coming first
+        let dtf = file(fork(vec![
+            op(Op::Reverse, bitcoin(6)),
+            op(Op::Sha256, bitcoin(5))
+        ]));
+        let shortest = prune(&dtf, Some(&roots(&dtf, &[5, 6])), &Limits::default(), Preference::Shortest).unwrap();
+        assert_eq!(serialized(&shortest), serialized(&file(op(Op::Sha256, bitcoin(5)))));
+
+        let dtf = file(fork(vec![
+            op(Op::Sha256, op(Op::Reverse, op(Op::Reverse, bitcoin(4)))),
+            op(Op::Sha256, bitcoin(4))
+        ]));
+        let earliest = prune(&dtf, Some(&roots(&dtf, &[4])), &Limits::default(), Preference::Earliest).unwrap();
+        assert_eq!(serialized(&earliest), serialized(&file(op(Op::Sha256, bitcoin(4)))));
+    }
+
+    #[test]
+    fn failures() {
+        let dtf = timestamp();
+        assert_eq!(
+            prune(&dtf, None, &Limits::default(), Preference::Shortest).err(),
+            Some(super::Error::NoHeaderSource)
+        );
+        assert_eq!(
+            prune(&dtf, Some(&roots(&dtf, &[])), &Limits::default(), Preference::Shortest).err(),
+            Some(super::Error::NoVerifiedAttestation)
+        );
+        let limits = Limits { max_depth: 3, ..Limits::default() };
+        match prune(&dtf, Some(&roots(&dtf, &[5, 7, 2])), &limits, Preference::Shortest) {
+            Err(super::Error::Tree(_)) => {}
+            other => panic!("expected a tree error, got {:?}", other.err())
+        }
+    }
+}
@@ -0,0 +1,1264 @@
+// OpenTimestamps Viewer
+// Written in 2017 by
+//   Andrew Poelstra <rust-ots@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Render
+//!
+//! Conversion of timestamps into rows of the HTML trace table
+//!
+
+use std::cmp;
+use std::collections::HashMap;
+use std::ops::Range;
+
+use chrono::DateTime;
+use ots::attestation::Attestation;
+use ots::hex::Hexed;
+use ots::op::Op;
+use ots::timestamp::{Step, StepData};
+
+use chain::{self, reversed, HeaderSource, Verification};
+use tree::{self, Limits, Visit};
+use tx::{self, Transaction};
+
+/// A piece of text on the rendered page. Everything is escaped by the
+/// template; `emphasis` only selects a style, never markup.
+#[derive(Clone, Debug, Serialize)]
+pub struct Segment {
+    text: String,
+    emphasis: bool
+}
+
+impl Segment {
+    fn plain<S: Into<String>>(text: S) -> Segment {
+        Segment { text: text.into(), emphasis: false }
+    }
+
+    fn emphasized<S: Into<String>>(text: S) -> Segment {
+        Segment { text: text.into(), emphasis: true }
+    }
+}
+
+/// Most attestations listed in the heading of a single branch
+const MAX_HEADING: usize = 4;
+
+/// Most forks on a single path that `render_tree` will display. Each one
+/// nests a branch inside another, which the templates render recursively.
+pub const MAX_FORK_NESTING: usize = 32;
+
+/// A row of the trace table
+#[derive(Clone, Debug, Serialize)]
+pub struct DisplayedStep {
+    /// Data output by this step, in hex, with any new data emphasized
+    hex: Vec<Segment>,
+    /// Description of the step, with important values emphasized
+    label: Vec<Segment>,
+    reason: String,
+    class: &'static str,
+    verification: Option<DisplayedVerification>,
+    /// The paths out of this step, if it is a fork
+    branches: Vec<DisplayedBranch>,
+    /// The individual operations, if this row stands for several of them
+    raw: Vec<DisplayedStep>,
+    /// Details of the transaction, if this row is a parsed transaction
+    transaction: Option<DisplayedTransaction>
+}
+
+/// A transaction which commits to a timestamp
+#[derive(Clone, Debug, Serialize)]
+pub struct DisplayedTransaction {
+    txid: String,
+    /// Whether the transaction is serialized with witnesses
+    segwit: bool,
+    version: u32,
+    lock_time: u32,
+    inputs: Vec<DisplayedTxIn>,
+    outputs: Vec<DisplayedTxOut>,
+    /// The serialized transaction, with the data from the previous step
+    /// emphasized
+    hex: Vec<Segment>
+}
+
+/// An input of a displayed transaction
+#[derive(Clone, Debug, Serialize)]
+pub struct DisplayedTxIn {
+    prev_txid: String,
+    prev_vout: u32,
+    script_sig: String,
+    sequence: String,
+    witness: Vec<String>
+}
+
+/// An output of a displayed transaction
+#[derive(Clone, Debug, Serialize)]
+pub struct DisplayedTxOut {
+    index: usize,
+    value: String,
+    /// The scriptPubKey, with the committed digest emphasized
+    script: Vec<Segment>,
+    kind: &'static str,
+    /// Whether this output contains the committed digest
+    commitment: bool
+}
+
+/// A sequence of steps, either the whole timestamp or one path out of a
+/// fork. Branches are nested as deep as forks are on a single path, which
+/// `render_tree` keeps to `MAX_FORK_NESTING`.
+#[derive(Clone, Debug, Serialize)]
+pub struct DisplayedBranch {
+    /// Position of this branch among the paths out of its fork, from 1
+    index: usize,
+    /// The first few attestations that this branch ends in
+    heading: Vec<DisplayedLeaf>,
+    /// Number of attestations this branch ends in beyond those in `heading`
+    more: usize,
+    steps: Vec<DisplayedStep>
+}
+
+impl DisplayedBranch {
+    fn new(index: usize) -> DisplayedBranch {
+        DisplayedBranch {
+            index: index,
+            heading: vec![],
+            more: 0,
+            steps: vec![]
+        }
+    }
+
+    /// Add an attestation to the heading, or just count it if it is full
+    fn add_leaf(&mut self, leaf: &DisplayedLeaf) {
+        if self.heading.len() < MAX_HEADING {
+            self.heading.push(leaf.clone());
+        } else {
+            self.more += 1;
+        }
+    }
+}
+
+/// A single path from the start digest to an attestation
+#[derive(Debug, Serialize)]
+pub struct DisplayedPath {
+    /// Position of this path among all paths in the timestamp, from 1
+    index: usize,
+    /// The attestation this path ends in
+    leaf: DisplayedLeaf,
+    /// Whether the steps of this path were rendered
+    shown: bool,
+    steps: Vec<DisplayedStep>
+}
+
+/// Short description of an attestation, for branch headings
+#[derive(Clone, Debug, Serialize)]
+pub struct DisplayedLeaf {
+    text: String,
+    class: &'static str
+}
+
+/// Result of checking a Bitcoin attestation
+#[derive(Clone, Debug, Serialize)]
+pub struct DisplayedVerification {
+    text: String,
+    class: &'static str,
+    attested: Option<DisplayedBlock>
+}
+
+/// A block whose header matched an attestation
+#[derive(Clone, Debug, Serialize)]
+pub struct DisplayedBlock {
+    height: usize,
+    block_hash: String,
+    time: String,
+    median_time_past: String,
+    // Raw timestamp, used only to pick the earliest block
+    #[serde(skip)]
+    unix_time: u32
+}
+
+/// Summary shown at the top of the page
+#[derive(Debug, Serialize)]
+pub struct DisplayedVerdict {
+    summary: String,
+    class: &'static str,
+    earliest: Option<DisplayedBlock>
+}
+
+/// Result of comparing a user-supplied document against a timestamp
+#[derive(Debug, Serialize)]
+pub struct DisplayedDocumentCheck {
+    summary: String,
+    class: &'static str
+}
+
+/// Format a UNIX timestamp for display
+fn format_time(time: u32) -> String {
+    match DateTime::from_timestamp(time as i64, 0) {
+        Some(time) => time.format("%Y-%m-%d %H:%M UTC").to_string(),
+        None => format!("{} seconds after the UNIX epoch", time)
+    }
+}
+
+/// Format a block height with thousands separators, e.g. 464,122
+pub fn format_height(height: usize) -> String {
+    let digits = height.to_string();
+    let mut ret = String::with_capacity(digits.len() + digits.len() / 3);
+    for (n, ch) in digits.chars().enumerate() {
+        if n > 0 && (digits.len() - n) % 3 == 0 {
+            ret.push(',');
+        }
+        ret.push(ch);
+    }
+    ret
+}
+
+/// Check a Bitcoin attestation against the configured header source
+fn render_verification(chain: Option<&dyn HeaderSource>, height: usize, merkle_root: &[u8]) -> DisplayedVerification {
+    let source = match chain {
+        Some(source) => source,
+        None => return DisplayedVerification {
+            text: "Not verified: no block header source is configured".to_owned(),
+            class: "verify_unknown",
+            attested: None
+        }
+    };
+    match chain::verify(source, height, merkle_root) {
+        Ok(Verification::Verified { header, median_time_past }) => DisplayedVerification {
+            text: format!("Verified: block {} has this Merkle root", height),
+            class: "verify_ok",
+            attested: Some(DisplayedBlock {
+                height: height,
+                block_hash: format!("{}", Hexed(&reversed(&header.block_hash()))),
+                time: format_time(header.time()),
+                median_time_past: format_time(median_time_past),
+                unix_time: header.time()
+            })
+        },
+        Ok(Verification::Mismatch(header)) => DisplayedVerification {
+            text: format!("MISMATCH: block {} has Merkle root {}", height, Hexed(&reversed(header.merkle_root()))),
+            class: "verify_bad",
+            attested: None
+        },
+        Ok(Verification::UnknownHeight) => DisplayedVerification {
+            text: format!("Not verified: block {} is not known to the block header source", height),
+            class: "verify_unknown",
+            attested: None
+        },
+        Err(e) => DisplayedVerification {
+            text: format!("Could not verify: {}", e),
+            class: "verify_unknown",
+            attested: None
+        }
+    }
+}
+
+/// Summarize the verification results of all Bitcoin attestations in a
+/// timestamp, using the earliest verified one if there is any
+pub fn render_verdict(root: &DisplayedBranch) -> DisplayedVerdict {
+    let mut verifications: Vec<&DisplayedVerification> = vec![];
+    let mut stack = vec![root];
+    while let Some(branch) = stack.pop() {
+        for step in &branch.steps {
+            if let Some(ref verification) = step.verification {
+                verifications.push(verification);
+            }
+            stack.extend(step.branches.iter());
+        }
+    }
+    let earliest = verifications.iter()
+        .filter_map(|v| v.attested.as_ref())
+        .min_by_key(|block| (block.height, block.unix_time));
+
+    if let Some(block) = earliest {
+        DisplayedVerdict {
+            summary: format!("This document existed no later than {} (block {})", block.time, format_height(block.height)),
+            class: "verdict_ok",
+            earliest: Some(block.clone())
+        }
+    } else if verifications.iter().any(|v| v.class == "verify_bad") {
+        DisplayedVerdict {
+            summary: "This timestamp's Bitcoin attestations do NOT match the Bitcoin block chain".to_owned(),
+            class: "verdict_bad",
+            earliest: None
+        }
+    } else if !verifications.is_empty() {
+        DisplayedVerdict {
+            summary: "This timestamp's Bitcoin attestations could not be verified".to_owned(),
+            class: "verdict_unknown",
+            earliest: None
+        }
+    } else {
+        DisplayedVerdict {
+            summary: "This timestamp has no Bitcoin attestations yet".to_owned(),
+            class: "verdict_unknown",
+            earliest: None
+        }
+    }
+}
+
+/// Compare the digest of a user-supplied document with the digest that a
+/// timestamp commits to
+pub fn render_document_check(document_digest: &[u8], start_digest: &[u8]) -> DisplayedDocumentCheck {
+    if document_digest == start_digest {
+        DisplayedDocumentCheck {
+            summary: "Your document matches this timestamp".to_owned(),
+            class: "verdict_ok"
+        }
+    } else {
+        DisplayedDocumentCheck {
+            summary: format!("Your document does NOT match this timestamp: its digest is {}", Hexed(document_digest)),
+            class: "verdict_bad"
+        }
+    }
+}
+
+/// Format a short prefix of some data, for labelling Append/Prepend steps
+pub fn short_hex(data: &[u8]) -> String {
+    if data.len() > 3 {
+        format!("{}...", Hexed(&data[..3]))
+    } else {
+        format!("{}", Hexed(data))
+    }
+}
+
+/// A step which hashes its input together with a sibling, as in a node
+/// of a Merkle tree
+struct MerkleNode<'a> {
+    /// Whether the sibling is on the left, i.e. prepended
+    sibling_left: bool,
+    sibling: &'a [u8],
+    /// Number of SHA256 operations following the Append or Prepend: one
+    /// in calendar trees, two in Bitcoin transaction trees
+    hashes: usize
+}
+
+/// Whether a step is a SHA256 operation
+fn is_sha256(step: &Step) -> bool {
+    match step.data {
+        StepData::Op(Op::Sha256) => true,
+        _ => false
+    }
+}
+
+/// Recognise an Append or Prepend of a 32-byte hash to another 32-byte
+/// hash followed by SHA256 or double-SHA256
+fn merkle_node<'a>(step: &'a Step, input: &[u8]) -> Option<MerkleNode<'a>> {
+    let (sibling_left, sibling) = match step.data {
+        StepData::Op(Op::Prepend(ref data)) => (true, data),
+        StepData::Op(Op::Append(ref data)) => (false, data),
+        _ => return None
+    };
+    if sibling.len() != 32 || input.len() != 32 {
+        return None;
+    }
+    // The end of a transaction is not a Merkle node, even if it happens
+    // to be the right length
+    if find_transaction(step, input).is_some() {
+        return None;
+    }
+    let first = match step.next.first() {
+        Some(next) if is_sha256(next) => next,
+        _ => return None
+    };
+    let hashes = match first.next.first() {
+        Some(next) if is_sha256(next) => 2,
+        _ => 1
+    };
+    Some(MerkleNode {
+        sibling_left: sibling_left,
+        sibling: sibling,
+        hashes: hashes
+    })
+}
+
+/// Length of the digest committed to by a transaction; calendars commit
+/// to the SHA256 root of their Merkle tree
+const COMMITMENT_LEN: usize = 32;
+
+/// A transaction formed by a step, committing to the step's input
+struct FoundTransaction {
+    tx: Transaction,
+    /// Location of the step's input in the serialized transaction
+    carried: Range<usize>,
+    /// Location of the committed digest in the serialized transaction
+    commitment: Range<usize>,
+    /// Index of the output whose script contains the commitment
+    output: usize
+}
+
+/// Recognise a step whose output is a transaction committing to its input.
+/// A calendar completes its transaction by adding the rest of it around
+/// the root of its Merkle tree, so the committed digest is the last part
+/// of the input if the step is an Append, or the first part if it is a
+/// Prepend. Since arbitrary data can happen to parse as a transaction, it
+/// only counts if the digest lies inside an output script.
+fn find_transaction(step: &Step, input: &[u8]) -> Option<FoundTransaction> {
+    if input.len() < COMMITMENT_LEN {
+        return None;
+    }
+    let (carried, commitment) = match step.data {
+        StepData::Op(Op::Append(_)) => {
+            (0..input.len(), input.len() - COMMITMENT_LEN..input.len())
+        }
+        StepData::Op(Op::Prepend(ref data)) => {
+            (data.len()..data.len() + input.len(), data.len()..data.len() + COMMITMENT_LEN)
+        }
+        _ => return None
+    };
+    let tx = tx::parse(&step.output)?;
+    let output = tx.output_containing(&commitment)?;
+    Some(FoundTransaction {
+        tx: tx,
+        carried: carried,
+        commitment: commitment,
+        output: output
+    })
+}
+
+/// Split some data into segments, emphasizing the given range
+fn highlighted(data: &[u8], range: &Range<usize>) -> Vec<Segment> {
+    let mut ret = vec![];
+    if range.start > 0 {
+        ret.push(Segment::plain(format!("{}", Hexed(&data[..range.start]))));
+    }
+    ret.push(Segment::emphasized(format!("{}", Hexed(&data[range.start..range.end]))));
+    if range.end < data.len() {
+        ret.push(Segment::plain(format!("{}", Hexed(&data[range.end..]))));
+    }
+    ret
+}
+
+/// Format an amount of satoshis in bitcoins
+fn format_value(value: u64) -> String {
+    format!("{}.{:08} BTC", value / 100_000_000, value % 100_000_000)
+}
+
+/// Name the standard form of an output script, if it has one
+fn script_kind(script: &[u8]) -> &'static str {
+    let len = script.len();
+    if len > 0 && script[0] == 0x6a {
+        "OP_RETURN"
+    } else if len == 25 && script[..3] == [0x76, 0xa9, 0x14] && script[23..] == [0x88, 0xac] {
+        "P2PKH"
+    } else if len == 23 && script[..2] == [0xa9, 0x14] && script[22] == 0x87 {
+        "P2SH"
+    } else if len == 22 && script[..2] == [0x00, 0x14] {
+        "P2WPKH"
+    } else if len == 34 && script[..2] == [0x00, 0x20] {
+        "P2WSH"
+    } else {
+        "Other"
+    }
+}
+
+/// Describe every part of a transaction found by `find_transaction`
+fn render_transaction(data: &[u8], found: &FoundTransaction) -> DisplayedTransaction {
+    let inputs = found.tx.inputs.iter().map(|input| DisplayedTxIn {
+        prev_txid: format!("{}", Hexed(&reversed(&input.prev_txid))),
+        prev_vout: input.prev_vout,
+        script_sig: format!("{}", Hexed(&data[input.script_sig.start..input.script_sig.end])),
+        sequence: format!("{:08x}", input.sequence),
+        witness: input.witness.iter().map(|item| format!("{}", Hexed(&data[item.start..item.end]))).collect()
+    }).collect();
+    let outputs = found.tx.outputs.iter().enumerate().map(|(n, output)| {
+        let script = &data[output.script_pubkey.start..output.script_pubkey.end];
+        DisplayedTxOut {
+            index: n,
+            value: format_value(output.value),
+            script: if n == found.output {
+                let start = output.script_pubkey.start;
+                highlighted(script, &(found.commitment.start - start..found.commitment.end - start))
+            } else {
+                vec![Segment::plain(format!("{}", Hexed(script)))]
+            },
+            kind: script_kind(script),
+            commitment: n == found.output
+        }
+    }).collect();
+    DisplayedTransaction {
+        txid: format!("{}", Hexed(&reversed(&found.tx.txid(data)))),
+        segwit: found.tx.segwit.is_some(),
+        version: found.tx.version,
+        lock_time: found.tx.lock_time,
+        inputs: inputs,
+        outputs: outputs,
+        hex: highlighted(data, &found.carried)
+    }
+}
+
+/// Add rows describing the transaction formed by a step, if it is one
+/// which commits to the step's input
+fn render_found_transaction(step: &Step, prev_data: &[u8], vec: &mut Vec<DisplayedStep>, chain: Option<&dyn HeaderSource>) {
+    let found = match find_transaction(step, prev_data) {
+        Some(found) => found,
+        None => return
+    };
+    let transaction = render_transaction(&step.output, &found);
+    vec.push(DisplayedStep {
+        hex: vec![],
+        label: vec![
+            Segment::plain(if found.tx.segwit.is_some() { "Bitcoin segwit transaction " } else { "Bitcoin transaction " }),
+            Segment::emphasized(transaction.txid.clone()),
+            Segment::plain(", committing in output "),
+            Segment::emphasized(found.output.to_string())
+        ],
+        reason: "(Parse TX)".to_owned(),
+        class: "step_parse",
+        verification: None,
+        branches: vec![],
+        raw: vec![],
+        transaction: Some(transaction)
+    });
+    vec.push(DisplayedStep {
+        hex: vec![],
+        label: vec![
+            Segment::plain("The calendar's Merkle tree ends in this transaction; the Bitcoin block's Merkle tree follows")
+        ],
+        reason: "Calendar to Bitcoin".to_owned(),
+        class: "step_boundary",
+        verification: None,
+        branches: vec![],
+        raw: vec![],
+        transaction: None
+    });
+    if let Some(position) = block_position(step) {
+        vec.push(render_block_position(&position, chain));
+    }
+}
+
+/// Most levels of a block's Merkle tree that we will follow
+const MAX_BLOCK_TREE_DEPTH: usize = 64;
+
+/// Where a transaction sits in its block, as encoded by the Merkle path
+/// from the transaction to the block's Bitcoin attestation
+struct BlockPosition {
+    /// Index of the transaction in the block
+    index: u64,
+    /// Number of levels of the block's Merkle tree
+    depth: usize,
+    height: usize
+}
+
+/// Follow the Merkle path from the step completing a transaction to the
+/// Bitcoin attestation, if it consists only of double-SHA256 Merkle nodes.
+/// At each level, a sibling on the left means we are the right child and
+/// so contributes a 1 bit to the transaction index.
+fn block_position(tx_step: &Step) -> Option<BlockPosition> {
+    // The txid is the double-SHA256 of the transaction
+    let first_hash = tx_step.next.first()?;
+    let txid = first_hash.next.first()?;
+    if !is_sha256(first_hash) || !is_sha256(txid) {
+        return None;
+    }
+
+    let mut current = txid.next.first()?;
+    let mut input = &txid.output[..];
+    let mut index = 0;
+    let mut depth = 0;
+    loop {
+        if let StepData::Attestation(Attestation::Bitcoin { height }) = current.data {
+            return Some(BlockPosition {
+                index: index,
+                depth: depth,
+                height: height
+            });
+        }
+        if depth >= MAX_BLOCK_TREE_DEPTH {
+            return None;
+        }
+        let node = merkle_node(current, input)?;
+        if node.hashes != 2 {
+            return None;
+        }
+        if node.sibling_left {
+            index |= 1u64 << depth;
+        }
+        depth += 1;
+        let second_hash = current.next.first()?.next.first()?;
+        input = &second_hash.output[..];
+        current = second_hash.next.first()?;
+    }
+}
+
+/// Describe the position of a transaction in its block, checking the depth
+/// of the Merkle tree against the block's transaction count if known
+fn render_block_position(position: &BlockPosition, chain: Option<&dyn HeaderSource>) -> DisplayedStep {
+    let verification = chain.map(|source| {
+        match source.tx_count(position.height) {
+            Ok(Some(count)) => {
+                // A tree with `count` leaves has ceil(log2(count)) levels
+                let expected = match (count as u64).checked_next_power_of_two() {
+                    Some(leaves) => leaves.trailing_zeros() as usize,
+                    None => 64
+                };
+                if position.depth == expected && position.index < count as u64 {
+                    DisplayedVerification {
+                        text: format!("Consistent with the {} transactions in block {}", count, position.height),
+                        class: "verify_ok",
+                        attested: None
+                    }
+                } else {
+                    DisplayedVerification {
+                        text: format!("INCONSISTENT: block {} has {} transactions, so its Merkle tree has depth {}", position.height, count, expected),
+                        class: "verify_bad",
+                        attested: None
+                    }
+                }
+            }
+            Ok(None) => DisplayedVerification {
+                text: format!("Not checked: the block header source does not know how many transactions block {} has", position.height),
+                class: "verify_unknown",
+                attested: None
+            },
+            Err(e) => DisplayedVerification {
+                text: format!("Could not check: {}", e),
+                class: "verify_unknown",
+                attested: None
+            }
+        }
+    });
+    DisplayedStep {
+        hex: vec![],
+        label: vec![
+            Segment::plain("Transaction "),
+            Segment::emphasized(position.index.to_string()),
+            Segment::plain(" of block "),
+            Segment::emphasized(position.height.to_string()),
+            Segment::plain(", whose Merkle tree has depth "),
+            Segment::emphasized(position.depth.to_string())
+        ],
+        reason: "(Merkle path)".to_owned(),
+        class: "step_parse",
+        verification: verification,
+        branches: vec![],
+        raw: vec![],
+        transaction: None
+    }
+}
+
+/// Render a single step, folding recognised Merkle tree nodes into a
+/// single row with the raw operations attached. `absorb` counts the steps
+/// still to be folded into the last row, and must start at zero.
+fn render_step(visit: &Visit, vec: &mut Vec<DisplayedStep>, chain: Option<&dyn HeaderSource>, absorb: &mut usize) -> Option<DisplayedLeaf> {
+    // Steps are visited in order along a path, so the operations making
+    // up a Merkle node immediately follow it
+    if *absorb > 0 {
+        *absorb -= 1;
+        let mut raw = vec![];
+        let leaf = render_raw_step(visit, &mut raw, chain);
+        if let Some(last) = vec.last_mut() {
+            last.raw.extend(raw);
+        }
+        return leaf;
+    }
+
+    match merkle_node(visit.step, visit.input) {
+        Some(node) => {
+            let mut raw = vec![];
+            render_raw_step(visit, &mut raw, chain);
+            let sibling = Segment::emphasized(format!("{}", Hexed(node.sibling)));
+            let own = Segment::plain(format!("{}", Hexed(visit.input)));
+            let (left, right) = if node.sibling_left { (sibling, own) } else { (own, sibling) };
+            vec.push(DisplayedStep {
+                hex: vec![],
+                label: vec![
+                    Segment::plain("Merkle node: left sibling "),
+                    left,
+                    Segment::plain(" / right sibling "),
+                    right
+                ],
+                reason: if node.hashes == 2 { "Merkle (SHA256d)".to_owned() } else { "Merkle (SHA256)".to_owned() },
+                class: "step_merkle",
+                verification: None,
+                branches: vec![],
+                raw: raw,
+                transaction: None
+            });
+            *absorb = node.hashes;
+            None
+        }
+        None => render_raw_step(visit, vec, chain)
+    }
+}
+
+/// Render a single step as one or more rows of the trace table, returning
+/// a description of it for branch headings if it is an attestation
+fn render_raw_step(visit: &Visit, vec: &mut Vec<DisplayedStep>, chain: Option<&dyn HeaderSource>) -> Option<DisplayedLeaf> {
+    let step = visit.step;
+    let prev_data = visit.input;
+    match step.data {
+        StepData::Fork => {
+            vec.push(DisplayedStep {
+                hex: vec![],
+                label: vec![
+                    Segment::plain("Fork into "),
+                    Segment::emphasized(step.next.len().to_string()),
+                    Segment::plain(" paths")
+                ],
+                reason: "Fork".to_owned(),
+                class: "step_fork",
+                verification: None,
+                branches: vec![],
+                raw: vec![],
+                transaction: None
+            });
+            None
+        }
+        StepData::Op(ref op) => {
+            match *op {
+                Op::Sha1 | Op::Sha256 | Op::Ripemd160 |
+                Op::Reverse | Op::Hexlify => {
+                    vec.push(DisplayedStep {
+                        hex: vec![Segment::plain(format!("{}", Hexed(&step.output)))],
+                        label: vec![],
+                        reason: format!("{}", op),
+                        class: "step_op",
+                        verification: None,
+                        branches: vec![],
+                        raw: vec![],
+                        transaction: None
+                    });
+                }
+                Op::Append(ref newdata) => {
+                    vec.push(DisplayedStep {
+                        hex: vec![
+                            Segment::plain(format!("{}", Hexed(prev_data))),
+                            Segment::emphasized(format!("{}", Hexed(newdata)))
+                        ],
+                        label: vec![],
+                        reason: format!("Append({})", short_hex(newdata)),
+                        class: "step_op",
+                        verification: None,
+                        branches: vec![],
+                        raw: vec![],
+                        transaction: None
+                    });
+                    render_found_transaction(step, prev_data, vec, chain);
+                }
+                Op::Prepend(ref newdata) => {
+                    vec.push(DisplayedStep {
+                        hex: vec![
+                            Segment::emphasized(format!("{}", Hexed(newdata))),
+                            Segment::plain(format!("{}", Hexed(prev_data)))
+                        ],
+                        label: vec![],
+                        reason: format!("Prepend({})", short_hex(newdata)),
+                        class: "step_op",
+                        verification: None,
+                        branches: vec![],
+                        raw: vec![],
+                        transaction: None
+                    });
+                    render_found_transaction(step, prev_data, vec, chain);
+                }
+            };
+            None
+        }
+        StepData::Attestation(ref attest) => {
+            let mut verification = None;
+            let (label, leaf) = match *attest {
+                Attestation::Unknown { ref tag, ref data } => (
+                    vec![
+                        Segment::plain("Unknown attestation "),
+                        Segment::emphasized(format!("{}", Hexed(tag))),
+                        Segment::plain("/"),
+                        Segment::emphasized(format!("{}", Hexed(data)))
+                    ],
+                    DisplayedLeaf {
+                        text: format!("Unknown attestation {}", Hexed(tag)),
+                        class: "leaf_unknown"
+                    }
+                ),
+                Attestation::Pending { ref uri } => (
+                    vec![
+                        Segment::plain("Pending attestation: server "),
+                        Segment::emphasized(uri.clone())
+                    ],
+                    DisplayedLeaf {
+                        text: format!("Pending at {}", uri),
+                        class: "leaf_pending"
+                    }
+                ),
+                Attestation::Bitcoin { height } => {
+                    let checked = render_verification(chain, height, prev_data);
+                    let leaf = DisplayedLeaf {
+                        text: format!("Bitcoin block {}", format_height(height)),
+                        class: checked.class
+                    };
+                    verification = Some(checked);
+                    (
+                        vec![
+                            Segment::plain("Merkle root "),
+                            Segment::emphasized(format!("{}", Hexed(&reversed(prev_data)))),
+                            Segment::plain(" of Bitcoin block "),
+                            Segment::emphasized(height.to_string())
+                        ],
+                        leaf
+                    )
+                }
+            };
+            vec.push(DisplayedStep {
+                hex: vec![],
+                label: label,
+                reason: "Attestation".to_owned(),
+                class: "step_attest",
+                verification: verification,
+                branches: vec![],
+                raw: vec![],
+                transaction: None
+            });
+            Some(leaf)
+        }
+    }
+}
+
+/// Render a timestamp as a tree of branches, one for each path out of
+/// every fork, failing if the timestamp is malformed, exceeds `limits` or
+/// has more than `MAX_FORK_NESTING` forks on some path
+pub fn render_tree(first_step: &Step, start_digest: &[u8], chain: Option<&dyn HeaderSource>, limits: &Limits) -> Result<DisplayedBranch, tree::Error> {
+    // Branches are rendered into a flat list, in which every branch comes
+    // after the one containing its fork, and only nested once complete.
+    // For each branch we record its parent and the row of the fork in it.
+    let mut branches = vec![(DisplayedBranch::new(1), 0)];
+    let mut parents = vec![None];
+    let mut index: HashMap<Vec<usize>, usize> = HashMap::new();
+    index.insert(vec![], 0);
+    let mut absorb = 0;
+    let mut nesting = 0;
+
+    tree::walk(first_step, start_digest, limits, |visit| {
+        nesting = cmp::max(nesting, visit.branch.len());
+        let current = index[&visit.branch];
+        if let Some(leaf) = render_step(visit, &mut branches[current].0.steps, chain, &mut absorb) {
+            branches[current].0.add_leaf(&leaf);
+        }
+        if let StepData::Fork = visit.step.data {
+            let row = branches[current].0.steps.len() - 1;
+            for n in 0..visit.step.next.len() {
+                let mut path = visit.branch.clone();
+                path.push(n);
+                index.insert(path, branches.len());
+                branches.push((DisplayedBranch::new(n + 1), row));
+                parents.push(Some(current));
+            }
+        }
+    })?;
+    if nesting > MAX_FORK_NESTING {
+        return Err(tree::Error::TooManyForks(MAX_FORK_NESTING));
+    }
+
+    let root = tree::assemble(branches.into_iter().zip(parents).collect(), |(mut branch, row), children: Vec<(DisplayedBranch, usize)>| {
+        for (child, child_row) in children {
+            for leaf in &child.heading {
+                branch.add_leaf(leaf);
+            }
+            branch.more += child.more;
+            branch.steps[child_row].branches.push(child);
+        }
+        (branch, row)
+    });
+    match root {
+        Some((branch, _)) => Ok(branch),
+        None => Ok(DisplayedBranch::new(1))
+    }
+}
+
+/// Render every path from the start digest to an attestation as its own
+/// linear proof. If `only` is given, just the path with that index has its
+/// steps rendered. Fails if the timestamp is malformed or exceeds `limits`,
+/// including if the paths together have more steps than `limits` allows.
+pub fn render_paths(first_step: &Step, start_digest: &[u8], chain: Option<&dyn HeaderSource>, limits: &Limits, only: Option<usize>) -> Result<Vec<DisplayedPath>, tree::Error> {
+    let mut paths = vec![];
+    // Rows rendered for each step on the path to the current one, with
+    // the depth of the step; forks are left out since each path takes only
+    // one of their branches, and steps folded into the row of an earlier
+    // step have no entry of their own
+    let mut rows: Vec<(usize, Vec<DisplayedStep>)> = vec![];
+    let mut total = 0;
+    let mut absorb = 0;
+    tree::walk(first_step, start_digest, limits, |visit| {
+        while rows.last().map(|&(depth, _)| depth >= visit.depth).unwrap_or(false) {
+            rows.pop();
+        }
+        let leaf = if absorb > 0 {
+            match rows.last_mut() {
+                Some(&mut (_, ref mut step_rows)) => render_step(visit, step_rows, chain, &mut absorb),
+                None => None
+            }
+        } else {
+            let mut step_rows = vec![];
+            let leaf = render_step(visit, &mut step_rows, chain, &mut absorb);
+            if let StepData::Fork = visit.step.data {
+                step_rows.clear();
+            }
+            rows.push((visit.depth, step_rows));
+            leaf
+        };
+
+        if let Some(leaf) = leaf {
+            let index = paths.len() + 1;
+            let shown = only.map(|n| n == index).unwrap_or(true);
+            let mut steps = vec![];
+            // Once over the limit we fail anyway, so stop copying
+            if shown && total <= limits.max_steps {
+                for &(_, ref step_rows) in &rows {
+                    steps.extend(step_rows.iter().cloned());
+                }
+                total += steps.len();
+            }
+            paths.push(DisplayedPath {
+                index: index,
+                leaf: leaf,
+                shown: shown,
+                steps: steps
+            });
+        }
+    })?;
+    if total > limits.max_steps {
+        return Err(tree::Error::TooLarge(limits.max_steps));
+    }
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use ots::op::Op;
+    use ots::timestamp::{Step, StepData};
+
+    use ots::DetachedTimestampFile;
+    use ots::hex::Hexed;
+
+    use chain::{Error, Header, HeaderSource};
+    use testutil::{bitcoin, file, fork, op, pending, DIGEST};
+    use tree::{self, Limits};
+
+    use super::{block_position, find_transaction, format_time, render_paths, render_tree};
+    use super::{DisplayedBranch, DisplayedStep, COMMITMENT_LEN, MAX_FORK_NESTING};
+
+    /// A transaction with one input and an OP_RETURN output committing to
+    /// 32 bytes of 0xaa, followed by a zero lock time
+    fn commitment_tx() -> Vec<u8> {
+        let mut tx = vec![0x01, 0x00, 0x00, 0x00, 0x01];
+        tx.extend_from_slice(&[0x11; 32]);
+        tx.extend_from_slice(&[0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff, 0x01]);
+        tx.extend_from_slice(&[0x00; 8]);
+        tx.extend_from_slice(&[0x22, 0x6a, 0x20]);
+        tx.extend_from_slice(&[0xaa; 32]);
+        tx.extend_from_slice(&[0x00; 4]);
+        tx
+    }
+
+    fn op_step(op: Op, output: Vec<u8>) -> Step {
+        Step {
+            data: StepData::Op(op),
+            output: output,
+            next: vec![]
+        }
+    }
+
+    #[test]
+    fn append_completes_transaction() {
+        let tx = commitment_tx();
+        let split = tx.len() - 4;
+        let step = op_step(Op::Append(tx[split..].to_vec()), tx.clone());
+        let found = find_transaction(&step, &tx[..split]).unwrap();
+        assert_eq!(found.carried, 0..split);
+        assert_eq!(found.commitment, split - COMMITMENT_LEN..split);
+        assert_eq!(found.output, 0);
+    }
+
+    #[test]
+    fn prepend_completes_transaction() {
+        let tx = commitment_tx();
+        let split = tx.len() - 4 - COMMITMENT_LEN;
+        let step = op_step(Op::Prepend(tx[..split].to_vec()), tx.clone());
+        let found = find_transaction(&step, &tx[split..]).unwrap();
+        assert_eq!(found.carried, split..tx.len());
+        assert_eq!(found.commitment, split..split + COMMITMENT_LEN);
+        assert_eq!(found.output, 0);
+    }
+
+    #[test]
+    fn short_commitment_refused() {
+        // The transaction still parses, but the input is too short to be
+        // the calendar's digest
+        let tx = commitment_tx();
+        let split = tx.len() - COMMITMENT_LEN + 1;
+        let step = op_step(Op::Prepend(tx[..split].to_vec()), tx.clone());
+        assert!(find_transaction(&step, &tx[split..]).is_none());
+    }
+
+    #[test]
+    fn commitment_outside_outputs_refused() {
+        // The last 32 bytes of the input run into the lock time
+        let tx = commitment_tx();
+        let split = tx.len() - 2;
+        let step = op_step(Op::Append(tx[split..].to_vec()), tx.clone());
+        assert!(find_transaction(&step, &tx[..split]).is_none());
+    }
+
+    #[test]
+    fn times() {
+        assert_eq!(format_time(0), "1970-01-01 00:00 UTC");
+        assert_eq!(format_time(1500000000), "2017-07-14 02:40 UTC");
+        assert_eq!(format_time(0xffff_ffff), "2106-02-07 06:28 UTC");
+    }
+
+    /// A tree of `forks` forks nested in one another, with an attestation
+    /// beside each inner fork and one at the bottom
+    fn nested_forks(forks: usize) -> Step {
+        let mut step = bitcoin(0);
+        for height in 1..forks + 1 {
+            step = fork(vec![bitcoin(height), step]);
+        }
+        step
+    }
+
+    #[test]
+    fn fork_nesting() {
+        let dtf = file(nested_forks(MAX_FORK_NESTING));
+        let rendered = render_tree(&dtf.timestamp.first_step, &DIGEST, None, &Limits::default()).unwrap();
+        assert_eq!(rendered.heading.len() + rendered.more, MAX_FORK_NESTING + 1);
+
+        let dtf = file(nested_forks(MAX_FORK_NESTING + 1));
+        assert_eq!(
+            render_tree(&dtf.timestamp.first_step, &DIGEST, None, &Limits::default()).err(),
+            Some(tree::Error::TooManyForks(MAX_FORK_NESTING))
+        );
+    }
+
+    fn render(dtf: &DetachedTimestampFile) -> DisplayedBranch {
+        render_tree(&dtf.timestamp.first_step, &dtf.timestamp.start_digest, None, &Limits::default()).unwrap()
+    }
+
+    /// The reason column of each row
+    fn reasons(steps: &[DisplayedStep]) -> Vec<String> {
+        steps.iter().map(|step| step.reason.clone()).collect()
+    }
+
+    #[test]
+    fn calendar_merkle_nodes() {
+        let sha256 = format!("{}", Op::Sha256);
+        let dtf = file(fork(vec![
+            op(Op::Append(vec![0x11; 32]), op(Op::Sha256, pending("https://a.pool.opentimestamps.org"))),
+            op(Op::Prepend(vec![0x22; 32]), op(Op::Sha256, op(Op::Sha256, bitcoin(5)))),
+            // Only the first SHA256 after a fork belongs to the node
+            op(Op::Prepend(vec![0x33; 32]), op(Op::Sha256, fork(vec![op(Op::Sha256, bitcoin(6)), bitcoin(7)]))),
+            // Siblings which are not 32 bytes are not Merkle nodes
+            op(Op::Append(vec![0x44; 31]), op(Op::Sha256, bitcoin(8))),
+            // Nor are nodes hashed with anything but SHA256
+            op(Op::Append(vec![0x55; 32]), op(Op::Sha1, bitcoin(9)))
+        ]));
+        let rendered = render(&dtf);
+        let branches = &rendered.steps[0].branches;
+        assert_eq!(branches.len(), 5);
+
+        let node = &branches[0].steps[0];
+        assert_eq!(reasons(&branches[0].steps), vec!["Merkle (SHA256)", "Attestation"]);
+        assert_eq!(reasons(&node.raw), vec!["Append(111111...)".to_owned(), sha256.clone()]);
+        // Appending puts the sibling on the right
+        assert_eq!(node.label[1].text, format!("{}", Hexed(&DIGEST)));
+        assert!(!node.label[1].emphasis);
+        assert_eq!(node.label[3].text, format!("{}", Hexed(&[0x11; 32])));
+        assert!(node.label[3].emphasis);
+
+        let node = &branches[1].steps[0];
+        assert_eq!(reasons(&branches[1].steps), vec!["Merkle (SHA256d)", "Attestation"]);
+        assert_eq!(reasons(&node.raw), vec!["Prepend(222222...)".to_owned(), sha256.clone(), sha256.clone()]);
+        assert_eq!(node.label[1].text, format!("{}", Hexed(&[0x22; 32])));
+        assert!(node.label[1].emphasis);
+
+        assert_eq!(reasons(&branches[2].steps), vec!["Merkle (SHA256)", "Fork"]);
+        assert_eq!(branches[2].steps[0].raw.len(), 2);
+        let inner = &branches[2].steps[1].branches;
+        assert_eq!(reasons(&inner[0].steps), vec![sha256.clone(), "Attestation".to_owned()]);
+        assert_eq!(reasons(&inner[1].steps), vec!["Attestation"]);
+
+        assert_eq!(reasons(&branches[3].steps), vec!["Append(444444...)".to_owned(), sha256.clone(), "Attestation".to_owned()]);
+        assert_eq!(reasons(&branches[4].steps), vec!["Append(555555...)".to_owned(), format!("{}", Op::Sha1), "Attestation".to_owned()]);
+        for branch in &branches[3..] {
+            assert!(branch.steps.iter().all(|step| step.raw.is_empty()));
+        }
+    }
+
+    /// A timestamp of `DIGEST` which is committed to by `commitment_tx`,
+    /// and so completes it, followed by the transaction's Merkle path
+    fn block_path(path: Step) -> DetachedTimestampFile {
+        let tx = commitment_tx();
+        let split = tx.len() - 4 - COMMITMENT_LEN;
+        file(op(Op::Append(vec![0x00; 4]), op(Op::Prepend(tx[..split].to_vec()), op(Op::Sha256, op(Op::Sha256, path)))))
+    }
+
+    #[test]
+    fn block_merkle_nodes() {
+        let sha256 = format!("{}", Op::Sha256);
+        let dtf = block_path(
+            op(Op::Append(vec![0x11; 32]), op(Op::Sha256, op(Op::Sha256,
+            op(Op::Prepend(vec![0x22; 32]), op(Op::Sha256, op(Op::Sha256,
+            bitcoin(100)))))))
+        );
+        let rendered = render(&dtf);
+        // The hashes making the txid are not part of any node
+        assert_eq!(reasons(&rendered.steps), vec![
+            "Append(000000...)".to_owned(),
+            "Prepend(010000...)".to_owned(),
+            "(Parse TX)".to_owned(),
+            "Calendar to Bitcoin".to_owned(),
+            "(Merkle path)".to_owned(),
+            sha256.clone(),
+            sha256.clone(),
+            "Merkle (SHA256d)".to_owned(),
+            "Merkle (SHA256d)".to_owned(),
+            "Attestation".to_owned()
+        ]);
+        assert_eq!(reasons(&rendered.steps[7].raw), vec!["Append(111111...)".to_owned(), sha256.clone(), sha256.clone()]);
+        assert_eq!(reasons(&rendered.steps[8].raw), vec!["Prepend(222222...)".to_owned(), sha256.clone(), sha256.clone()]);
+        assert!(rendered.steps[2].transaction.is_some());
+    }
+
+    /// A header source which knows only how many transactions each block has
+    struct TxCount(usize);
+
+    impl HeaderSource for TxCount {
+        fn header_at(&self, _height: usize) -> Result<Option<Header>, Error> {
+            Ok(None)
+        }
+
+        fn tx_count(&self, _height: usize) -> Result<Option<usize>, Error> {
+            Ok(Some(self.0))
+        }
+    }
+
+    /// A double-SHA256 Merkle node, with the sibling on the left if `left`
+    fn node(left: bool, next: Step) -> Step {
+        let sibling = vec![0x11; 32];
+        let op_kind = if left { Op::Prepend(sibling) } else { Op::Append(sibling) };
+        op(op_kind, op(Op::Sha256, op(Op::Sha256, next)))
+    }
+
+    #[test]
+    fn transaction_position() {
+        // Right, then left, then right child: index 0b101
+        let dtf = block_path(node(true, node(false, node(true, bitcoin(100)))));
+        let tx_step = &dtf.timestamp.first_step.next[0];
+        let position = block_position(tx_step).unwrap();
+        assert_eq!(position.index, 5);
+        assert_eq!(position.depth, 3);
+        assert_eq!(position.height, 100);
+
+        // Straight to the attestation: the only transaction in its block
+        let dtf = block_path(bitcoin(100));
+        let position = block_position(&dtf.timestamp.first_step.next[0]).unwrap();
+        assert_eq!((position.index, position.depth), (0, 0));
+
+        // A single SHA256 node is not part of a block's Merkle tree
+        let dtf = block_path(op(Op::Append(vec![0x11; 32]), op(Op::Sha256, bitcoin(100))));
+        assert!(block_position(&dtf.timestamp.first_step.next[0]).is_none());
+        let dtf = block_path(node(true, pending("https://a.pool.opentimestamps.org")));
+        assert!(block_position(&dtf.timestamp.first_step.next[0]).is_none());
+    }
+
+    #[test]
+    fn transaction_count_check() {
+        let dtf = block_path(node(true, node(false, node(true, bitcoin(100)))));
+        let check = |count: usize| {
+            let rendered = render_tree(&dtf.timestamp.first_step, &DIGEST, Some(&TxCount(count)), &Limits::default()).unwrap();
+            let row = rendered.steps.iter().find(|step| step.reason == "(Merkle path)").unwrap();
+            assert_eq!(row.label[1].text, "5");
+            assert_eq!(row.label[5].text, "3");
+            row.verification.clone().unwrap()
+        };
+        // Trees of 6 to 8 transactions have depth 3, and index 5 fits
+        for &count in &[6, 7, 8] {
+            let verification = check(count);
+            assert_eq!(verification.class, "verify_ok");
+            assert_eq!(verification.text, format!("Consistent with the {} transactions in block 100", count));
+        }
+        // Index 5 does not fit, even though the depth is right
+        let verification = check(5);
+        assert_eq!(verification.class, "verify_bad");
+        assert_eq!(verification.text, "INCONSISTENT: block 100 has 5 transactions, so its Merkle tree has depth 3");
+        // The depth is wrong
+        assert_eq!(check(9).text, "INCONSISTENT: block 100 has 9 transactions, so its Merkle tree has depth 4");
+        assert_eq!(check(4).text, "INCONSISTENT: block 100 has 4 transactions, so its Merkle tree has depth 2");
+        assert_eq!(check(usize::MAX).class, "verify_bad");
+
+        let rendered = render_tree(&dtf.timestamp.first_step, &DIGEST, None, &Limits::default()).unwrap();
+        let row = rendered.steps.iter().find(|step| step.reason == "(Merkle path)").unwrap();
+        assert!(row.verification.is_none());
+    }
+
+    #[test]
+    fn one_path_per_attestation() {
+        let sha256 = format!("{}", Op::Sha256);
+        let dtf = file(fork(vec![
+            op(Op::Append(vec![0x01]), bitcoin(1)),
+            fork(vec![
+                pending("https://a.pool.opentimestamps.org"),
+                op(Op::Sha256, bitcoin(2))
+            ])
+        ]));
+        let paths = render_paths(&dtf.timestamp.first_step, &DIGEST, None, &Limits::default(), None).unwrap();
+        assert_eq!(paths.len(), 3);
+        assert_eq!(paths.iter().map(|path| path.index).collect::<Vec<_>>(), vec![1, 2, 3]);
+        assert_eq!(paths.iter().map(|path| path.leaf.text.clone()).collect::<Vec<_>>(), vec![
+            "Bitcoin block 1",
+            "Pending at https://a.pool.opentimestamps.org",
+            "Bitcoin block 2"
+        ]);
+        assert!(paths.iter().all(|path| path.shown));
+        // Forks are left out of every path
+        assert_eq!(reasons(&paths[0].steps), vec!["Append(01)", "Attestation"]);
+        assert_eq!(reasons(&paths[1].steps), vec!["Attestation"]);
+        assert_eq!(reasons(&paths[2].steps), vec![sha256, "Attestation".to_owned()]);
+    }
+
+    #[test]
+    fn only_selected_path() {
+        let dtf = file(fork(vec![
+            op(Op::Append(vec![0x01]), bitcoin(1)),
+            fork(vec![
+                pending("https://a.pool.opentimestamps.org"),
+                op(Op::Sha256, bitcoin(2))
+            ])
+        ]));
+        let paths = render_paths(&dtf.timestamp.first_step, &DIGEST, None, &Limits::default(), Some(2)).unwrap();
+        assert_eq!(paths.len(), 3);
+        assert_eq!(paths.iter().map(|path| path.shown).collect::<Vec<_>>(), vec![false, true, false]);
+        assert!(paths[0].steps.is_empty());
+        assert_eq!(reasons(&paths[1].steps), vec!["Attestation"]);
+        assert!(paths[2].steps.is_empty());
+
+        // Every path is still listed to choose from
+        let paths = render_paths(&dtf.timestamp.first_step, &DIGEST, None, &Limits::default(), Some(4)).unwrap();
+        assert_eq!(paths.len(), 3);
+        assert!(paths.iter().all(|path| !path.shown && path.steps.is_empty()));
+    }
+
+    #[test]
+    fn paths_size_limit() {
+        // Seven steps, but each of the three paths repeats the first three
+        let dtf = file(op(Op::Append(vec![0x01]), op(Op::Append(vec![0x02]), op(Op::Append(vec![0x03]), fork(vec![
+            bitcoin(1),
+            bitcoin(2),
+            bitcoin(3)
+        ])))));
+        let limits = Limits { max_steps: 10, ..Limits::default() };
+        assert_eq!(
+            render_paths(&dtf.timestamp.first_step, &DIGEST, None, &limits, None).err(),
+            Some(tree::Error::TooLarge(10))
+        );
+        let paths = render_paths(&dtf.timestamp.first_step, &DIGEST, None, &limits, Some(3)).unwrap();
+        assert_eq!(reasons(&paths[2].steps), vec!["Append(01)", "Append(02)", "Append(03)", "Attestation"]);
+        let limits = Limits { max_steps: 12, ..Limits::default() };
+        assert_eq!(render_paths(&dtf.timestamp.first_step, &DIGEST, None, &limits, None).unwrap().len(), 3);
+    }
+}
@@ -0,0 +1,222 @@
+// OpenTimestamps Viewer
+// Written in 2017 by
+//   Andrew Poelstra <rust-ots@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Scheduler
+//!
+//! Background worker which periodically upgrades cached timestamps that
+//! have only pending attestations
+//!
+
+use std::collections::{HashMap, HashSet};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use cache::{Cache, DocId};
+use calendar::{self, Calendars};
+
+/// Longest we will wait between attempts to upgrade a single timestamp
+const MAX_BACKOFF: u64 = 86400;
+
+/// Upgrade attempts made so far for a single timestamp
+struct Backoff {
+    failures: u32,
+    next_attempt: Instant
+}
+
+/// The background upgrade worker
+pub struct Scheduler {
+    cache: Cache,
+    calendars: Calendars,
+    interval: Duration,
+    backoff: HashMap<DocId, Backoff>
+}
+
+impl Scheduler {
+    /// Create a new scheduler which scans the cache every `interval`
+    pub fn new(cache: Cache, calendars: Calendars, interval: Duration) -> Scheduler {
+        Scheduler {
+            cache: cache,
+            calendars: calendars,
+            interval: interval,
+            backoff: HashMap::new()
+        }
+    }
+
+    /// Start the worker in a new thread
+    pub fn spawn(self) -> thread::JoinHandle<()> {
+        thread::spawn(move || {
+            let mut sched = self;
+            loop {
+                sched.scan();
+                thread::sleep(sched.interval);
+            }
+        })
+    }
+
+    /// How long to wait before trying again after `failures` failed attempts
+    fn delay(&self, failures: u32) -> Duration {
+        let secs = self.interval.as_secs().saturating_mul(1 << failures.min(16));
+        Duration::from_secs(secs.min(MAX_BACKOFF))
+    }
+
+    /// Try to upgrade every eligible timestamp in the cache
+    fn scan(&mut self) {
+        let ids: HashSet<DocId> = match self.cache.ids() {
+            Ok(ids) => ids.into_iter().filter(|id| !self.cache.is_linked(id)).collect(),
+            Err(e) => {
+                println!("Upgrade scheduler: failed to read cache: {}", e);
+                return;
+            }
+        };
+        // Forget timestamps which have been deleted or superseded
+        self.backoff.retain(|id, _| ids.contains(id));
+
+        let now = Instant::now();
+        for id in ids {
+            if let Some(backoff) = self.backoff.get(&id) {
+                if backoff.next_attempt > now {
+                    continue;
+                }
+            }
+            self.try_upgrade(id);
+        }
+    }
+
+    /// Try to upgrade a single cached timestamp, if it is still pending
+    fn try_upgrade(&mut self, id: DocId) {
+        let mut dtf = match self.cache.load(&id) {
+            Ok(dtf) => dtf,
+            Err(_) => return
+        };
+        if !calendar::all_pending(&dtf.timestamp.first_step) {
+            self.backoff.remove(&id);
+            return;
+        }
+
+        let attempts = self.calendars.upgrade(&mut dtf);
+        if attempts.iter().any(|a| a.outcome == calendar::Outcome::Upgraded) {
+            match self.cache.store(&dtf).and_then(|new_id| self.cache.link(&id, &new_id).map(|_| new_id)) {
+                Ok(new_id) => {
+                    println!("Upgrade scheduler: upgraded {} to {}", id, new_id);
+                    self.backoff.remove(&id);
+                }
+                Err(e) => println!("Upgrade scheduler: failed to store upgrade of {}: {}", id, e)
+            }
+        } else {
+            let failures = self.backoff.get(&id).map(|b| b.failures + 1).unwrap_or(1);
+            let next_attempt = Instant::now() + self.delay(failures);
+            self.backoff.insert(id, Backoff {
+                failures: failures,
+                next_attempt: next_attempt
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+    use std::path::{Path, PathBuf};
+    use std::time::{Duration, Instant};
+
+    use ots::op::Op;
+
+    use cache::Cache;
+    use calendar::Calendars;
+    use testutil::{bitcoin, file, op, pending};
+    use tree::Limits;
+
+    use super::Scheduler;
+
+    /// A fresh directory for a cache, unique to the calling test
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("ots-viewer-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// A scheduler over a cache in `dir`, which may contact no calendars,
+    /// so fails to upgrade anything
+    fn scheduler(dir: &Path, interval: u64) -> Scheduler {
+        let calendars = Calendars::new(vec![], Duration::from_secs(1), Limits::default()).unwrap();
+        Scheduler::new(Cache::new(dir), calendars, Duration::from_secs(interval))
+    }
+
+    #[test]
+    fn delays() {
+        let dir = temp_dir("scheduler-delays");
+        let sched = scheduler(&dir, 10);
+        assert_eq!(sched.delay(0), Duration::from_secs(10));
+        assert_eq!(sched.delay(1), Duration::from_secs(20));
+        assert_eq!(sched.delay(3), Duration::from_secs(80));
+        assert_eq!(sched.delay(13), Duration::from_secs(81920));
+        // Capped at a day, however many failures
+        assert_eq!(sched.delay(14), Duration::from_secs(86400));
+        assert_eq!(sched.delay(100), Duration::from_secs(86400));
+        assert_eq!(scheduler(&dir, u64::MAX).delay(100), Duration::from_secs(86400));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn backoff() {
+        let dir = temp_dir("scheduler-backoff");
+        let cache = Cache::new(&dir);
+        let pending_id = cache.store(&file(op(Op::Sha256, pending("https://a.pool.opentimestamps.org")))).unwrap();
+        let complete_id = cache.store(&file(op(Op::Sha256, bitcoin(100)))).unwrap();
+        let mut sched = scheduler(&dir, 10);
+
+        // Only the pending timestamp is tried, and fails
+        let start = Instant::now();
+        sched.scan();
+        assert_eq!(sched.backoff.len(), 1);
+        assert_eq!(sched.backoff[&pending_id].failures, 1);
+        assert!(sched.backoff[&pending_id].next_attempt >= start + Duration::from_secs(20));
+        assert!(!sched.backoff.contains_key(&complete_id));
+
+        // It is not tried again until its delay is up
+        sched.scan();
+        assert_eq!(sched.backoff[&pending_id].failures, 1);
+        sched.backoff.get_mut(&pending_id).unwrap().next_attempt = Instant::now();
+        let start = Instant::now();
+        sched.scan();
+        assert_eq!(sched.backoff[&pending_id].failures, 2);
+        assert!(sched.backoff[&pending_id].next_attempt >= start + Duration::from_secs(40));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn forgotten() {
+        let dir = temp_dir("scheduler-forgotten");
+        let cache = Cache::new(&dir);
+        let deleted = cache.store(&file(op(Op::Sha256, pending("https://a.pool.opentimestamps.org")))).unwrap();
+        let linked = cache.store(&file(op(Op::Sha1, pending("https://a.pool.opentimestamps.org")))).unwrap();
+        let kept = cache.store(&file(op(Op::Ripemd160, pending("https://a.pool.opentimestamps.org")))).unwrap();
+        let mut sched = scheduler(&dir, 10);
+        sched.scan();
+        assert_eq!(sched.backoff.len(), 3);
+
+        // Deleted and superseded timestamps are dropped on the next pass,
+        // even while waiting out their delay
+        fs::remove_file(cache.path(&deleted)).unwrap();
+        let new_id = cache.store(&file(op(Op::Sha1, bitcoin(100)))).unwrap();
+        cache.link(&linked, &new_id).unwrap();
+        sched.scan();
+        assert_eq!(sched.backoff.len(), 1);
+        assert!(sched.backoff.contains_key(&kept));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
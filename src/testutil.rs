@@ -0,0 +1,98 @@
+// OpenTimestamps Viewer
+// Written in 2017 by
+//   Andrew Poelstra <rust-ots@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Test Utilities
+//!
+//! Construction of timestamps for unit tests. Trees are written from the
+//! attestations up, leaving outputs empty, and `file` fills them in.
+//!
+
+// Also compiled into the server's tests, which use only some of these
+#![allow(dead_code)]
+
+use ots::DetachedTimestampFile;
+use ots::attestation::Attestation;
+use ots::op::Op;
+use ots::ser::DigestType;
+use ots::timestamp::{Step, StepData, Timestamp};
+
+/// Start digest of every timestamp made by `file`
+pub const DIGEST: [u8; 32] = [0xaa; 32];
+
+/// An operation followed by `next`
+pub fn op(op: Op, next: Step) -> Step {
+    Step {
+        data: StepData::Op(op),
+        output: vec![],
+        next: vec![next]
+    }
+}
+
+/// A fork into each of `next`
+pub fn fork(next: Vec<Step>) -> Step {
+    Step {
+        data: StepData::Fork,
+        output: vec![],
+        next: next
+    }
+}
+
+/// An attestation, ending a path
+pub fn attest(attestation: Attestation) -> Step {
+    Step {
+        data: StepData::Attestation(attestation),
+        output: vec![],
+        next: vec![]
+    }
+}
+
+/// A Bitcoin attestation at the given height
+pub fn bitcoin(height: usize) -> Step {
+    attest(Attestation::Bitcoin { height: height })
+}
+
+/// A pending attestation from the given calendar
+pub fn pending(uri: &str) -> Step {
+    attest(Attestation::Pending { uri: uri.to_owned() })
+}
+
+/// A SHA256 timestamp of `DIGEST` starting with `first_step`, with the
+/// output of every step computed
+pub fn file(mut first_step: Step) -> DetachedTimestampFile {
+    let mut stack = vec![(&mut first_step, DIGEST.to_vec())];
+    while let Some((step, input)) = stack.pop() {
+        let Step { ref data, ref mut output, ref mut next } = *step;
+        *output = match *data {
+            StepData::Op(ref op) => op.execute(&input),
+            _ => input
+        };
+        for next in next.iter_mut() {
+            stack.push((next, output.clone()));
+        }
+    }
+    DetachedTimestampFile {
+        digest_type: DigestType::Sha256,
+        timestamp: Timestamp {
+            start_digest: DIGEST.to_vec(),
+            first_step: first_step
+        }
+    }
+}
+
+/// A timestamp serialized as it would be stored, for comparing trees
+pub fn serialized(dtf: &DetachedTimestampFile) -> Vec<u8> {
+    let mut data = vec![];
+    dtf.to_writer(&mut data).unwrap();
+    data
+}
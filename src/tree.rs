@@ -0,0 +1,240 @@
+// OpenTimestamps Viewer
+// Written in 2017 by
+//   Andrew Poelstra <rust-ots@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Tree
+//!
+//! Bounded, non-recursive traversal of timestamp trees. Timestamps come
+//! from untrusted uploads, so nothing here may panic or use stack space
+//! proportional to the size of the tree.
+//!
+
+use std::{error, fmt, mem};
+
+use ots::timestamp::{Step, StepData};
+
+/// Limits on the size of trees we are willing to process
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Limits {
+    /// Maximum number of steps on any path from the root
+    pub max_depth: usize,
+    /// Maximum number of steps in the whole tree
+    pub max_steps: usize
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_depth: 1024,
+            max_steps: 65536
+        }
+    }
+}
+
+/// Errors encountered while traversing a tree
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Error {
+    /// Some path through the tree exceeds the depth limit
+    TooDeep(usize),
+    /// The tree has more steps than the size limit
+    TooLarge(usize),
+    /// An operation is not followed by any step
+    MissingNext,
+    /// Some path through the tree passes through more forks than we can
+    /// display nested in one another
+    TooManyForks(usize)
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::TooDeep(limit) => write!(f, "timestamp is nested more than {} steps deep", limit),
+            Error::TooLarge(limit) => write!(f, "timestamp has more than {} steps", limit),
+            Error::MissingNext => f.write_str("timestamp has an operation with nothing following it"),
+            Error::TooManyForks(limit) => write!(f, "timestamp has more than {} forks on a single path", limit)
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::TooDeep(_) => "timestamp too deep",
+            Error::TooLarge(_) => "timestamp too large",
+            Error::MissingNext => "operation not followed by any step",
+            Error::TooManyForks(_) => "timestamp forks too often"
+        }
+    }
+}
+
+/// A step encountered during a traversal
+pub struct Visit<'a> {
+    /// The step itself
+    pub step: &'a Step,
+    /// The data the step operates on, i.e. the output of the step before it
+    pub input: &'a [u8],
+    /// Number of steps between this one and the root
+    pub depth: usize,
+    /// Index of the branch taken at each fork on the way to this step
+    pub branch: Vec<usize>
+}
+
+/// Visit every step of the tree in depth-first order, i.e. the order in
+/// which they appear in the serialized timestamp, stopping with an error
+/// if the tree is malformed or exceeds `limits`
+pub fn walk<'a, F>(first_step: &'a Step, start_digest: &'a [u8], limits: &Limits, mut visit: F) -> Result<(), Error>
+    where F: FnMut(&Visit<'a>)
+{
+    let mut stack = vec![Visit {
+        step: first_step,
+        input: start_digest,
+        depth: 0,
+        branch: vec![]
+    }];
+    let mut count = 0;
+    while let Some(current) = stack.pop() {
+        count += 1;
+        if count > limits.max_steps {
+            return Err(Error::TooLarge(limits.max_steps));
+        }
+        if current.depth >= limits.max_depth {
+            return Err(Error::TooDeep(limits.max_depth));
+        }
+
+        match current.step.data {
+            StepData::Fork => {
+                // Push in reverse so that the first branch is visited first
+                for (n, next) in current.step.next.iter().enumerate().rev() {
+                    let mut branch = current.branch.clone();
+                    branch.push(n);
+                    stack.push(Visit {
+                        step: next,
+                        input: current.input,
+                        depth: current.depth + 1,
+                        branch: branch
+                    });
+                }
+            }
+            StepData::Op(_) => {
+                match current.step.next.first() {
+                    Some(next) => stack.push(Visit {
+                        step: next,
+                        input: &current.step.output,
+                        depth: current.depth + 1,
+                        branch: current.branch.clone()
+                    }),
+                    None => return Err(Error::MissingNext)
+                }
+            }
+            StepData::Attestation(_) => {}
+        }
+        visit(&current);
+    }
+    Ok(())
+}
+
+/// Check that a tree is well-formed and within `limits`
+pub fn check(first_step: &Step, start_digest: &[u8], limits: &Limits) -> Result<(), Error> {
+    walk(first_step, start_digest, limits, |_| {})
+}
+
+/// Build a value for every node of a tree stored as a flat list of nodes
+/// and the indices of their parents, in which every node comes after its
+/// parent and the first node is the root. Working backward means `build`
+/// sees every node only once the values of its children, which it is given
+/// in order, are complete. Returns the value of the root.
+pub fn assemble<N, T, F>(mut nodes: Vec<(N, Option<usize>)>, mut build: F) -> Option<T>
+    where F: FnMut(N, Vec<T>) -> T
+{
+    let mut children: Vec<Vec<T>> = nodes.iter().map(|_| vec![]).collect();
+    let mut root = None;
+    while let Some((node, parent)) = nodes.pop() {
+        let mut done = mem::replace(&mut children[nodes.len()], vec![]);
+        // Children are completed last first
+        done.reverse();
+        let value = build(node, done);
+        match parent {
+            Some(parent) => children[parent].push(value),
+            None => root = Some(value)
+        }
+    }
+    root
+}
+
+/// Build a value for every step of the tree from the step and the values
+/// of the steps following it, as in `assemble`, returning the value of the
+/// first step. Fails if the tree is malformed or exceeds `limits`.
+pub fn fold<'a, T, F>(first_step: &'a Step, start_digest: &'a [u8], limits: &Limits, build: F) -> Result<T, Error>
+    where F: FnMut(Visit<'a>, Vec<T>) -> T
+{
+    let mut nodes = vec![];
+    // Steps are visited depth-first, so the parent of each step is the
+    // most recent one visited at the level above it
+    let mut path: Vec<usize> = vec![];
+    walk(first_step, start_digest, limits, |visit| {
+        path.truncate(visit.depth);
+        let parent = path.last().cloned();
+        path.push(nodes.len());
+        nodes.push((Visit {
+            step: visit.step,
+            input: visit.input,
+            depth: visit.depth,
+            branch: visit.branch.clone()
+        }, parent));
+    })?;
+    // The walk only succeeds if it visited the first step, so there is
+    // always a root
+    assemble(nodes, build).ok_or(Error::TooLarge(limits.max_steps))
+}
+
+#[cfg(test)]
+mod tests {
+    use ots::attestation::Attestation;
+    use ots::op::Op;
+    use ots::timestamp::StepData;
+
+    use testutil::{bitcoin, file, fork, op, DIGEST};
+
+    use super::{assemble, fold, Limits};
+
+    #[test]
+    fn assemble_order() {
+        // 0 -> (1 -> 3, 2)
+        let nodes = vec![(0, None), (1, Some(0)), (3, Some(1)), (2, Some(0))];
+        let built = assemble(nodes, |n, children: Vec<String>| {
+            if children.is_empty() {
+                format!("{}", n)
+            } else {
+                format!("{}({})", n, children.join(","))
+            }
+        });
+        assert_eq!(built, Some("0(1(3),2)".to_owned()));
+        assert_eq!(assemble(vec![], |n: usize, _: Vec<usize>| n), None);
+    }
+
+    #[test]
+    fn fold_tree() {
+        let dtf = file(fork(vec![op(Op::Sha256, bitcoin(1)), bitcoin(2), bitcoin(3)]));
+        let fork = &dtf.timestamp.first_step;
+        let leaves = fold(fork, &DIGEST, &Limits::default(), |visit, children: Vec<Vec<usize>>| {
+            match visit.step.data {
+                StepData::Attestation(Attestation::Bitcoin { height }) => vec![height],
+                _ => children.into_iter().flatten().collect()
+            }
+        });
+        assert_eq!(leaves, Ok(vec![1, 2, 3]));
+
+        let limits = Limits { max_steps: 3, ..Limits::default() };
+        assert!(fold(fork, &DIGEST, &limits, |_, _: Vec<()>| ()).is_err());
+    }
+}
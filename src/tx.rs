@@ -0,0 +1,394 @@
+// OpenTimestamps Viewer
+// Written in 2017 by
+//   Andrew Poelstra <rust-ots@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Transactions
+//!
+//! Parsing of Bitcoin transactions which records where in the serialized
+//! transaction each script lies, so that commitments can be located. Both
+//! the legacy layout and the segwit layout with marker, flag and witnesses
+//! are understood.
+//!
+
+use std::ops::Range;
+
+use chain;
+
+/// A transaction input
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct TxIn {
+    /// Txid of the output being spent, in internal byte order
+    pub prev_txid: Vec<u8>,
+    /// Index of the output being spent
+    pub prev_vout: u32,
+    /// Location of the scriptSig in the serialized transaction
+    pub script_sig: Range<usize>,
+    pub sequence: u32,
+    /// Locations of the witness stack items, if any
+    pub witness: Vec<Range<usize>>
+}
+
+/// A transaction output
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct TxOut {
+    /// Value in satoshis
+    pub value: u64,
+    /// Location of the scriptPubKey in the serialized transaction
+    pub script_pubkey: Range<usize>
+}
+
+/// A parsed transaction
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Transaction {
+    pub version: u32,
+    pub inputs: Vec<TxIn>,
+    pub outputs: Vec<TxOut>,
+    pub lock_time: u32,
+    /// Location of the segwit marker and flag, and of all the witnesses,
+    /// if the transaction is serialized with witnesses
+    pub segwit: Option<(Range<usize>, Range<usize>)>
+}
+
+impl Transaction {
+    /// The txid, which for segwit transactions is the hash of the
+    /// serialization without marker, flag or witnesses, in internal byte
+    /// order. `data` must be the data the transaction was parsed from.
+    pub fn txid(&self, data: &[u8]) -> [u8; 32] {
+        match self.segwit {
+            Some((ref marker, ref witnesses)) => {
+                let mut stripped = Vec::with_capacity(data.len());
+                stripped.extend_from_slice(&data[..marker.start]);
+                stripped.extend_from_slice(&data[marker.end..witnesses.start]);
+                stripped.extend_from_slice(&data[witnesses.end..]);
+                chain::sha256d(&stripped)
+            }
+            None => chain::sha256d(data)
+        }
+    }
+
+    /// Index of the output whose scriptPubKey contains all of `range`
+    pub fn output_containing(&self, range: &Range<usize>) -> Option<usize> {
+        self.outputs.iter().position(|output| {
+            output.script_pubkey.start <= range.start && range.end <= output.script_pubkey.end
+        })
+    }
+}
+
+/// Reads little-endian integers and byte strings, tracking its position
+struct Cursor<'a> {
+    data: &'a [u8],
+    pos: usize
+}
+
+impl<'a> Cursor<'a> {
+    /// Skip `len` bytes, returning their location
+    fn take(&mut self, len: usize) -> Option<Range<usize>> {
+        if self.data.len() - self.pos < len {
+            return None;
+        }
+        let range = self.pos..self.pos + len;
+        self.pos += len;
+        Some(range)
+    }
+
+    /// Read a little-endian integer of `len` bytes
+    fn read_le(&mut self, len: usize) -> Option<u64> {
+        let range = self.take(len)?;
+        let mut ret = 0;
+        for (n, byte) in self.data[range].iter().enumerate() {
+            ret |= (*byte as u64) << (8 * n);
+        }
+        Some(ret)
+    }
+
+    /// Read a Bitcoin variable-length integer
+    fn read_varint(&mut self) -> Option<u64> {
+        match self.read_le(1)? {
+            0xfd => self.read_le(2),
+            0xfe => self.read_le(4),
+            0xff => self.read_le(8),
+            n => Some(n)
+        }
+    }
+
+    /// Read a length-prefixed byte string, returning its location
+    fn read_bytes(&mut self) -> Option<Range<usize>> {
+        let len = self.read_varint()?;
+        if len > (self.data.len() - self.pos) as u64 {
+            return None;
+        }
+        self.take(len as usize)
+    }
+
+    /// Read a count of items each at least `min_size` bytes long, refusing
+    /// counts which could not possibly fit in the remaining data
+    fn read_count(&mut self, min_size: usize) -> Option<usize> {
+        let count = self.read_varint()?;
+        if count > ((self.data.len() - self.pos) / min_size) as u64 {
+            return None;
+        }
+        Some(count as usize)
+    }
+
+    /// Look at the next `len` bytes without moving past them
+    fn peek(&self, len: usize) -> Option<&'a [u8]> {
+        if self.data.len() - self.pos < len {
+            None
+        } else {
+            Some(&self.data[self.pos..self.pos + len])
+        }
+    }
+}
+
+/// Parse a serialized transaction, which must use every byte of `data`.
+/// Transactions without inputs or outputs are refused, which also means
+/// that a zero where the input count should be is always a segwit marker.
+pub fn parse(data: &[u8]) -> Option<Transaction> {
+    let mut cursor = Cursor {
+        data: data,
+        pos: 0
+    };
+    let version = cursor.read_le(4)? as u32;
+    let marker = match cursor.peek(2) {
+        Some(bytes) if bytes == [0x00, 0x01] => Some(cursor.take(2)?),
+        _ => None
+    };
+
+    // Each input is at least 41 bytes and each output at least 9
+    let n_inputs = cursor.read_count(41)?;
+    let mut inputs = Vec::with_capacity(n_inputs);
+    for _ in 0..n_inputs {
+        let prev_txid = cursor.take(32)?;
+        inputs.push(TxIn {
+            prev_txid: data[prev_txid].to_vec(),
+            prev_vout: cursor.read_le(4)? as u32,
+            script_sig: cursor.read_bytes()?,
+            sequence: cursor.read_le(4)? as u32,
+            witness: vec![]
+        });
+    }
+
+    let n_outputs = cursor.read_count(9)?;
+    let mut outputs = Vec::with_capacity(n_outputs);
+    for _ in 0..n_outputs {
+        outputs.push(TxOut {
+            value: cursor.read_le(8)?,
+            script_pubkey: cursor.read_bytes()?
+        });
+    }
+
+    let segwit = match marker {
+        Some(marker) => {
+            let start = cursor.pos;
+            for input in &mut inputs {
+                // Each witness item is at least its length byte
+                let n_items = cursor.read_count(1)?;
+                for _ in 0..n_items {
+                    input.witness.push(cursor.read_bytes()?);
+                }
+            }
+            // A segwit serialization with no witnesses is not valid
+            if inputs.iter().all(|input| input.witness.is_empty()) {
+                return None;
+            }
+            Some((marker, start..cursor.pos))
+        }
+        None => None
+    };
+
+    let lock_time = cursor.read_le(4)? as u32;
+    if inputs.is_empty() || outputs.is_empty() || cursor.pos != data.len() {
+        return None;
+    }
+    Some(Transaction {
+        version: version,
+        inputs: inputs,
+        outputs: outputs,
+        lock_time: lock_time,
+        segwit: segwit
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use hex;
+
+    use chain::reversed;
+    use super::parse;
+
+    /// A legacy transaction with a P2PKH output and an OP_RETURN output,
+    /// as a calendar's transaction has, from mainnet
+    const LEGACY: &'static str = "01000000010c7196428403d8b0c88fcb3ee8d64f56f55c8973c9ab7dd106bb4f3527f5888d000000006a4730440220503a696f55f2c00eee2ac5e65b17767cd88ed04866b5637d3c1d5d996a70656d02202c9aff698f343abb6d176704beda63fcdec503133ea4f6a5216b7f925fa9910c0121024d89b5a13d6521388969209df27a8469bd565aff10e8d42cef931fad5121bfb8ffffffff02b825b404000000001976a914ef79e7ee9fff98bcfd08473d2b76b02a48f8c69088ac0000000000000000296a2732363030393438363937313732333132373633313032313332353630353838373931323132373000000000";
+    const LEGACY_TXID: &'static str = "971ed48a62c143bbd9c87f4bafa2ef213cfa106c6e140f111931d0be307468dd";
+
+    /// A segwit transaction spending one P2WPKH output, from mainnet
+    const SEGWIT: &'static str = "02000000000101595895ea20179de87052b4046dfe6fd515860505d6511a9004cf12a1f93cac7c0100000000ffffffff01deb807000000000017a9140f3444e271620c736808aa7b33e370bd87cb5a078702483045022100fb60dad8df4af2841adc0346638c16d0b8035f5e3f3753b88db122e70c79f9370220756e6633b17fd2710e626347d28d60b0a2d6cbb41de51740644b9fb3ba7751040121028fa937ca8cba2197a37c007176ed8941055d3bcb8627d085e94553e62f057dcc00000000";
+    const SEGWIT_TXID: &'static str = "f5864806e3565c34d1b41e716f72609d00b55ea5eac5b924c9719a842ef42206";
+    const SEGWIT_WTXID: &'static str = "80b7d8a82d5d5bf92905b06f2014dd699e03837ca172e3a59d51426ebbe3e7f5";
+
+    fn txid_hex(txid: &[u8]) -> String {
+        hex::encode(reversed(txid))
+    }
+
+    #[test]
+    fn legacy() {
+        let data = hex::decode(LEGACY).unwrap();
+        let tx = parse(&data).unwrap();
+        assert_eq!(tx.version, 1);
+        assert_eq!(tx.lock_time, 0);
+        assert!(tx.segwit.is_none());
+        assert_eq!(tx.inputs.len(), 1);
+        assert_eq!(txid_hex(&tx.inputs[0].prev_txid), "8d88f527354fbb06d17dabc973895cf5564fd6e83ecb8fc8b0d803844296710c");
+        assert_eq!(tx.inputs[0].prev_vout, 0);
+        assert_eq!(tx.inputs[0].sequence, 0xffffffff);
+        assert!(tx.inputs[0].witness.is_empty());
+
+        assert_eq!(tx.outputs.len(), 2);
+        assert_eq!(tx.outputs[0].value, 78915000);
+        assert_eq!(&data[tx.outputs[0].script_pubkey.clone()][..3], &[0x76, 0xa9, 0x14]);
+        assert_eq!(tx.outputs[1].value, 0);
+        let op_return = tx.outputs[1].script_pubkey.clone();
+        assert_eq!(op_return.len(), 41);
+        assert_eq!(data[op_return.start], 0x6a);
+        assert_eq!(op_return.end, data.len() - 4);
+
+        // A commitment inside the OP_RETURN is found; one straddling the
+        // end of the script is not
+        assert_eq!(tx.output_containing(&(op_return.start + 2..op_return.end)), Some(1));
+        assert_eq!(tx.output_containing(&(op_return.start + 2..op_return.end + 1)), None);
+
+        assert_eq!(txid_hex(&tx.txid(&data)), LEGACY_TXID);
+    }
+
+    #[test]
+    fn segwit() {
+        let data = hex::decode(SEGWIT).unwrap();
+        let tx = parse(&data).unwrap();
+        assert_eq!(tx.version, 2);
+        assert_eq!(tx.inputs.len(), 1);
+        assert_eq!(txid_hex(&tx.inputs[0].prev_txid), "7cac3cf9a112cf04901a51d605058615d56ffe6d04b45270e89d1720ea955859");
+        assert_eq!(tx.inputs[0].prev_vout, 1);
+        assert_eq!(tx.outputs.len(), 1);
+        assert_eq!(tx.outputs[0].value, 506078);
+
+        let (marker, witnesses) = tx.segwit.clone().unwrap();
+        assert_eq!(marker, 4..6);
+        assert_eq!(witnesses.end, data.len() - 4);
+        // A signature and a public key
+        assert_eq!(tx.inputs[0].witness.len(), 2);
+        assert_eq!(tx.inputs[0].witness[0].len(), 72);
+        assert_eq!(tx.inputs[0].witness[1].len(), 33);
+    }
+
+    #[test]
+    fn segwit_txid_excludes_witnesses() {
+        let data = hex::decode(SEGWIT).unwrap();
+        let tx = parse(&data).unwrap();
+        assert_eq!(txid_hex(&tx.txid(&data)), SEGWIT_TXID);
+        // Hashing everything gives the wtxid instead
+        assert_eq!(txid_hex(&::chain::sha256d(&data)), SEGWIT_WTXID);
+    }
+
+    #[test]
+    fn truncated() {
+        // Every field boundary, and everywhere in between
+        for tx in &[LEGACY, SEGWIT] {
+            let data = hex::decode(tx).unwrap();
+            for len in 0..data.len() {
+                assert!(parse(&data[..len]).is_none(), "parsed {} of {} bytes", len, data.len());
+            }
+        }
+    }
+
+    #[test]
+    fn trailing_data() {
+        let mut data = hex::decode(LEGACY).unwrap();
+        data.push(0);
+        assert!(parse(&data).is_none());
+    }
+
+    #[test]
+    fn oversized_varints() {
+        // Input count of 2^64 - 1
+        let data = hex::decode("01000000ffffffffffffffffff").unwrap();
+        assert!(parse(&data).is_none());
+
+        // Input count which would fit in memory but not in the data
+        let mut data = hex::decode(LEGACY).unwrap();
+        data[4] = 0xfe;
+        data.splice(5..5, vec![0xff, 0xff, 0xff]);
+        assert!(parse(&data).is_none());
+
+        // scriptSig length of 2^32 - 1
+        let mut data = hex::decode(LEGACY).unwrap();
+        data[41] = 0xfe;
+        data.splice(42..42, vec![0xff, 0xff, 0xff, 0xff]);
+        assert!(parse(&data).is_none());
+
+        // scriptPubKey length of 2^64 - 1, at the end of the data
+        let data = hex::decode("01000000010c7196428403d8b0c88fcb3ee8d64f56f55c8973c9ab7dd106bb4f3527f5888d0000000000ffffffff010000000000000000ffffffffffffffffff").unwrap();
+        assert!(parse(&data).is_none());
+    }
+
+    #[test]
+    fn empty_input_list() {
+        // No inputs followed by two outputs cannot be mistaken for a
+        // segwit marker, but is refused anyway
+        let data = hex::decode("0100000000020000000000000000016a0000000000000000016a00000000").unwrap();
+        assert!(parse(&data).is_none());
+        // Nor are outputs optional
+        let data = hex::decode("01000000010c7196428403d8b0c88fcb3ee8d64f56f55c8973c9ab7dd106bb4f3527f5888d0000000000ffffffff0000000000").unwrap();
+        assert!(parse(&data).is_none());
+    }
+
+    #[test]
+    fn zero_inputs_or_marker() {
+        // No inputs and one output would be serialized starting 00 01, just
+        // like a segwit marker and flag. It is read as a marker, after
+        // which the output does not parse as inputs.
+        let data = hex::decode("0100000000010000000000000000016a00000000").unwrap();
+        assert!(parse(&data).is_none());
+
+        // A real marker followed by inputs and outputs parses as segwit, but
+        // only if some input actually has a witness
+        let data = hex::decode(SEGWIT).unwrap();
+        let tx = parse(&data).unwrap();
+        assert!(tx.segwit.is_some());
+        let (_, witnesses) = tx.segwit.unwrap();
+        let mut empty = data[..witnesses.start].to_vec();
+        empty.push(0x00);
+        empty.extend_from_slice(&data[witnesses.end..]);
+        assert!(parse(&empty).is_none());
+    }
+
+    #[test]
+    fn truncated_witness_stack() {
+        let data = hex::decode(SEGWIT).unwrap();
+        let tx = parse(&data).unwrap();
+        let (_, witnesses) = tx.segwit.unwrap();
+        // Claim a third witness item which is not there
+        let mut extra = data.clone();
+        extra[witnesses.start] = 0x03;
+        assert!(parse(&extra).is_none());
+        // Claim a longer last item than there is
+        let last = tx.inputs[0].witness[1].clone();
+        let mut long = data.clone();
+        long[last.start - 1] = 0x40;
+        assert!(parse(&long).is_none());
+        // Drop the last item but still claim two
+        let mut short = data.clone();
+        short.drain(last.start - 1..last.end);
+        assert!(parse(&short).is_none());
+    }
+}
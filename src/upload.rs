@@ -0,0 +1,239 @@
+// OpenTimestamps Viewer
+// Written in 2017 by
+//   Andrew Poelstra <rust-ots@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Upload
+//!
+//! Parsing of uploaded timestamps, and the errors that can occur doing so
+//!
+
+use std::{fmt, io};
+use std::io::Read;
+
+use ots::{self, DetachedTimestampFile};
+use rocket::Request;
+use rocket::http::Status;
+use rocket::response::{self, status, Responder};
+use rocket_contrib::templates::Template;
+
+use cache;
+use ots_viewer::{merge, tree};
+
+/// Largest timestamp file we will accept, whether uploaded through the
+/// form or the API
+pub const SIZE_LIMIT: u64 = 32768;
+
+/// Magic bytes at the start of every detached timestamp file
+pub const MAGIC: &'static [u8] = b"\x00OpenTimestamps\x00\x00Proof\x00\xbf\x89\xe2\xe8\x84\xe8\x92\x94";
+
+/// Errors encountered while handling an upload
+#[derive(Debug)]
+pub enum Error {
+    /// The multipart form data could not be parsed
+    Multipart(String),
+    /// A required form field was not provided
+    MissingField(&'static str),
+    /// The uploaded file exceeded the given size limit
+    TooLarge(u64),
+    /// The uploaded file does not start with the timestamp magic bytes
+    NotTimestamp,
+    /// The document digest typed in by the user is not valid hex
+    BadDigest,
+    /// I/O error on our side while handling the upload
+    Io(io::Error),
+    /// The uploaded file is not a valid timestamp
+    Parse {
+        /// Number of bytes read before the parser gave up
+        offset: u64,
+        /// The parser's complaint
+        error: ots::error::Error
+    },
+    /// The timestamps could not be merged
+    Merge(merge::Error),
+    /// The timestamps could not be compared
+    Compare(merge::Error),
+    /// The timestamp exceeds the limits on the size of trees we process
+    Limits(tree::Error),
+    /// The timestamp could not be stored in the cache
+    Storage(cache::Error)
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+impl From<merge::Error> for Error {
+    fn from(e: merge::Error) -> Error {
+        Error::Merge(e)
+    }
+}
+
+impl From<cache::Error> for Error {
+    fn from(e: cache::Error) -> Error {
+        Error::Storage(e)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Multipart(ref s) => write!(f, "Malformed upload: {}", s),
+            Error::MissingField(field) => write!(f, "No {} was provided", field),
+            Error::TooLarge(limit) => write!(f, "Upload exceeds the size limit of {} bytes", limit),
+            Error::NotTimestamp => f.write_str("Not a timestamp file: this does not look like an .ots file"),
+            Error::BadDigest => f.write_str("The document digest must be given in hex"),
+            Error::Io(ref e) => write!(f, "Failed to handle upload: {}", e),
+            Error::Parse { offset, ref error } => write!(f, "Not a valid timestamp file (error near byte {}): {}", offset, error),
+            Error::Merge(ref e) => write!(f, "Cannot merge timestamps: {}", e),
+            Error::Compare(ref e) => write!(f, "Cannot compare timestamps: {}", e),
+            Error::Limits(ref e) => write!(f, "Timestamp is too large to process: {}", e),
+            Error::Storage(ref e) => write!(f, "Failed to store timestamp: {}", e)
+        }
+    }
+}
+
+impl Error {
+    /// The HTTP status to report this error with
+    pub fn status(&self) -> Status {
+        match *self {
+            Error::Multipart(_) | Error::MissingField(_) | Error::NotTimestamp |
+            Error::BadDigest | Error::Parse { .. } | Error::Merge(_) | Error::Compare(_) |
+            Error::Limits(_) => Status::BadRequest,
+            Error::TooLarge(_) => Status::PayloadTooLarge,
+            Error::Io(_) | Error::Storage(_) => Status::InternalServerError
+        }
+    }
+
+    /// An error page for this error, under the given title
+    pub fn page(self, title: &str) -> status::Custom<Template> {
+        println!("Rejected upload: {}", self);
+        let page = ::error_page(title, self.to_string());
+        status::Custom(self.status(), page)
+    }
+}
+
+impl<'r> Responder<'r> for Error {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        self.page("Upload Timestamp").respond_to(request)
+    }
+}
+
+/// Check that the start of an upload looks like a timestamp file
+pub fn check_magic(data: &[u8]) -> Result<(), Error> {
+    if data == MAGIC {
+        Ok(())
+    } else {
+        Err(Error::NotTimestamp)
+    }
+}
+
+/// Reader which counts the bytes read through it
+struct CountingReader<R> {
+    inner: R,
+    count: u64
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+}
+
+/// Parse a timestamp, reporting roughly where parsing failed if it did
+pub fn parse<R: Read>(reader: R) -> Result<DetachedTimestampFile, Error> {
+    let mut counter = CountingReader {
+        inner: reader,
+        count: 0
+    };
+    match DetachedTimestampFile::from_reader(&mut counter) {
+        Ok(dtf) => Ok(dtf),
+        Err(e) => Err(Error::Parse {
+            offset: counter.count,
+            error: e
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use rocket::http::Status;
+
+    use cache;
+    use ots_viewer::{merge, tree};
+    use testutil::{bitcoin, file, serialized};
+
+    use super::{check_magic, parse, Error, MAGIC};
+
+    #[test]
+    fn parse_offset() {
+        let data = serialized(&file(bitcoin(100)));
+        assert!(parse(&data[..]).is_ok());
+        // Whatever is cut off, the parser reads everything there is
+        for &len in &[0, 10, MAGIC.len(), MAGIC.len() + 5, data.len() - 1] {
+            match parse(&data[..len]) {
+                Err(Error::Parse { offset, .. }) => assert_eq!(offset, len as u64),
+                Err(e) => panic!("expected a parse error, got {}", e),
+                Ok(_) => panic!("parsed a timestamp cut off after {} bytes", len)
+            }
+        }
+    }
+
+    #[test]
+    fn magic() {
+        let data = serialized(&file(bitcoin(100)));
+        assert!(check_magic(MAGIC).is_ok());
+        assert!(check_magic(&data[..MAGIC.len()]).is_ok());
+
+        let mut bad = MAGIC.to_vec();
+        bad[1] = b'o';
+        match check_magic(&bad) {
+            Err(Error::NotTimestamp) => {}
+            _ => panic!("accepted bad magic")
+        }
+        match check_magic(&MAGIC[..MAGIC.len() - 1]) {
+            Err(Error::NotTimestamp) => {}
+            _ => panic!("accepted short magic")
+        }
+    }
+
+    #[test]
+    fn statuses() {
+        let parse_error = match parse(&b"not a timestamp"[..]) {
+            Err(e) => e,
+            Ok(_) => panic!("parsed junk")
+        };
+        let cases = vec![
+            (Error::Multipart("bad boundary".to_owned()), Status::BadRequest),
+            (Error::MissingField("timestamp file"), Status::BadRequest),
+            (Error::TooLarge(32768), Status::PayloadTooLarge),
+            (Error::NotTimestamp, Status::BadRequest),
+            (Error::BadDigest, Status::BadRequest),
+            (Error::Io(io::Error::new(io::ErrorKind::Other, "disk full")), Status::InternalServerError),
+            (parse_error, Status::BadRequest),
+            (Error::Merge(merge::Error::DigestMismatch), Status::BadRequest),
+            (Error::Compare(merge::Error::DigestTypeMismatch), Status::BadRequest),
+            (Error::Limits(tree::Error::TooLarge(65536)), Status::BadRequest),
+            (Error::Storage(cache::Error::Io(io::Error::new(io::ErrorKind::Other, "disk full"))), Status::InternalServerError)
+        ];
+        for (error, status) in cases {
+            assert_eq!(error.status(), status, "status of {}", error);
+        }
+    }
+}